@@ -0,0 +1,171 @@
+use bevy::prelude::*;
+
+use crate::sdf::SdfNode;
+
+/// Volume affected by a brush, centred on the hit point
+/// Cylinders and cones are aligned on the axis of the edit (the apex of cones points along it),
+/// cubes on the axes of the entity
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum BrushShape {
+    #[default]
+    Sphere,
+    Cube,
+    Cylinder,
+    Cone,
+}
+
+/// How the effect of a brush decreases from its centre to its border
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Falloff {
+    Linear,
+    #[default]
+    Smooth,
+    /// Same effect everywhere inside the brush
+    Constant,
+}
+
+impl Falloff {
+    /// Weight of the brush at a normalized distance from its centre (1 being its border)
+    pub fn weight(&self, t: f32) -> f32 {
+        if t > 1. {
+            return 0.;
+        }
+        let t = t.max(0.);
+        match self {
+            Falloff::Linear => 1. - t,
+            Falloff::Smooth => 1. - t * t * (3. - 2. * t),
+            Falloff::Constant => 1.,
+        }
+    }
+}
+
+/// Shape and intensity of the edits made to procedural entities
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Brush {
+    pub shape: BrushShape,
+    // in world units (see scaled to get it in voxels)
+    pub radius: f32,
+    // fraction of the way the voxels at the centre of the brush move towards the result of an
+    // edit, repeated edits accumulating their effect
+    pub strength: f32,
+    pub falloff: Falloff,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            shape: BrushShape::default(),
+            radius: 0.4,
+            strength: 0.25,
+            falloff: Falloff::default(),
+        }
+    }
+}
+
+impl Brush {
+    /// Radii (in world units) and strengths the brush can be set to, from the keyboard or the UI
+    pub const RADIUS_RANGE: std::ops::RangeInclusive<f32> = 0.05..=2.0;
+    pub const STRENGTH_RANGE: std::ops::RangeInclusive<f32> = 0.05..=1.0;
+
+    /// Same brush with its radius expressed in the local space of an entity with the given scale
+    pub fn scaled(&self, scale: f32) -> Self {
+        Self {
+            radius: self.radius / scale,
+            ..*self
+        }
+    }
+
+    /// Distance from the centre of the brush to offset, relative to the size of the shape along
+    /// that direction (1 being the border of the brush)
+    pub fn normalized_distance(&self, offset: Vec3, axis: Vec3) -> f32 {
+        let r = self.radius.max(f32::EPSILON);
+        let axis = axis.normalize_or(Vec3::Y);
+        let height = offset.dot(axis);
+        let radial = (offset - axis * height).length();
+        match self.shape {
+            BrushShape::Sphere => offset.length() / r,
+            BrushShape::Cube => offset.abs().max_element() / r,
+            BrushShape::Cylinder => (radial / r).max(height.abs() / r),
+            BrushShape::Cone => {
+                // base of radius r at -r along the axis, apex at +r
+                let cone_radius = (r - height) / 2.;
+                if cone_radius <= f32::EPSILON {
+                    return f32::INFINITY;
+                }
+                (radial / cone_radius).max(height.abs() / r)
+            }
+        }
+    }
+
+    /// Half size of a box (aligned on the axes of the entity) containing the brush, whatever its
+    /// axis
+    pub fn half_extent(&self) -> f32 {
+        match self.shape {
+            BrushShape::Sphere | BrushShape::Cube => self.radius,
+            // the rims of their caps go up to a corner of the box containing them
+            BrushShape::Cylinder | BrushShape::Cone => self.radius * std::f32::consts::SQRT_2,
+        }
+    }
+
+    /// Signed distance function of the brush centred on center, cylinders and cones being
+    /// aligned on axis
+    pub fn sdf(&self, center: Vec3, axis: Vec3) -> SdfNode {
+        let r = self.radius;
+        let rotation = Quat::from_rotation_arc(Vec3::Y, axis.normalize_or(Vec3::Y));
+        match self.shape {
+            BrushShape::Sphere => SdfNode::sphere(center, r),
+            BrushShape::Cube => SdfNode::cuboid(center, Vec3::splat(r)),
+            BrushShape::Cylinder => {
+                SdfNode::cylinder(Vec3::ZERO, r, r).transformed(rotation, center)
+            }
+            // same cone as normalized_distance: base at -r along the axis, apex at +r
+            BrushShape::Cone => {
+                SdfNode::cone(Vec3::new(0., -r, 0.), r, 2. * r).transformed(rotation, center)
+            }
+        }
+    }
+
+    /// Weight of the brush at offset from its centre, between 0 and 1
+    pub fn weight(&self, offset: Vec3, axis: Vec3) -> f32 {
+        self.falloff.weight(self.normalized_distance(offset, axis))
+    }
+}
+
+/// Brush used by the next edits, changed by the input and the UI
+#[derive(Resource, Default)]
+pub struct ActiveBrush(pub Brush);
+
+/// Changes the active brush with the keyboard (the brush window of the UI shows it):
+/// B cycles through the shapes, N through the falloffs, + and - change the radius, [ and ] the
+/// strength
+pub fn brush_input_system(
+    mut brush: ResMut<ActiveBrush>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    let brush = &mut brush.0;
+    if keyboard_input.just_pressed(KeyCode::KeyB) {
+        brush.shape = match brush.shape {
+            BrushShape::Sphere => BrushShape::Cube,
+            BrushShape::Cube => BrushShape::Cylinder,
+            BrushShape::Cylinder => BrushShape::Cone,
+            BrushShape::Cone => BrushShape::Sphere,
+        };
+    } else if keyboard_input.just_pressed(KeyCode::KeyN) {
+        brush.falloff = match brush.falloff {
+            Falloff::Linear => Falloff::Smooth,
+            Falloff::Smooth => Falloff::Constant,
+            Falloff::Constant => Falloff::Linear,
+        };
+    } else if keyboard_input.just_pressed(KeyCode::Equal) {
+        brush.radius *= 1.25;
+    } else if keyboard_input.just_pressed(KeyCode::Minus) {
+        brush.radius /= 1.25;
+    } else if keyboard_input.just_pressed(KeyCode::BracketRight) {
+        brush.strength += 0.05;
+    } else if keyboard_input.just_pressed(KeyCode::BracketLeft) {
+        brush.strength -= 0.05;
+    }
+    let (radius, strength) = (Brush::RADIUS_RANGE, Brush::STRENGTH_RANGE);
+    brush.radius = brush.radius.clamp(*radius.start(), *radius.end());
+    brush.strength = brush.strength.clamp(*strength.start(), *strength.end());
+}
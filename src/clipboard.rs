@@ -0,0 +1,267 @@
+use bevy::prelude::*;
+
+use crate::brush::Brush;
+use crate::common::coords::ICoords;
+use crate::common::field_extent::FieldExtent;
+use crate::common::voxels::Voxel;
+use crate::procedural_entity::ProceduralEntity;
+
+/// How pasted voxels are combined with the voxels of the entity
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum BlendMode {
+    /// The pasted voxels replace the ones of the entity
+    Replace,
+    /// Adds the solid part of the clip to the entity
+    #[default]
+    Union,
+    /// Carves the solid part of the clip out of the entity
+    Subtract,
+}
+
+/// Voxels copied from the field of an entity
+#[derive(Clone)]
+pub struct VoxelClip {
+    pub extent: FieldExtent,
+    // None outside of the copied shape
+    pub voxels: Vec<Option<Voxel>>,
+    // position the clip was copied around, in voxels of the clip
+    pub origin: Vec3,
+    // axis of the brush it was copied with, pastes turn it onto the axis they are made along
+    pub axis: Vec3,
+}
+
+impl VoxelClip {
+    /// Number of voxels of the copied shape
+    pub fn voxel_count(&self) -> usize {
+        self.voxels.iter().filter(|v| v.is_some()).count()
+    }
+
+    /// Copies the voxels of an entity between min and max (included), with its origin at their
+    /// centre and its axis pointing down
+    pub fn from_box(entity: &ProceduralEntity, min: ICoords, max: ICoords) -> Self {
+        let center = Vec3::new(
+            (min.x + max.x) as f32,
+            (min.y + max.y) as f32,
+            (min.z + max.z) as f32,
+        ) / 2.;
+        Self::copy(entity, (min, max), center, Vec3::NEG_Y, |_| true)
+    }
+
+    /// Copies the voxels of an entity inside a brush (whose radius is in voxels) centred on
+    /// `center`, cylinders and cones being aligned on axis
+    pub fn from_brush(entity: &ProceduralEntity, center: Vec3, axis: Vec3, brush: &Brush) -> Self {
+        let half_extent = Vec3::splat(brush.half_extent());
+        let (min, max) = (
+            (center - half_extent).floor(),
+            (center + half_extent).ceil(),
+        );
+        let bounds = (
+            ICoords {
+                x: min.x as i32,
+                y: min.y as i32,
+                z: min.z as i32,
+            },
+            ICoords {
+                x: max.x as i32,
+                y: max.y as i32,
+                z: max.z as i32,
+            },
+        );
+        Self::copy(entity, bounds, center, axis, |pos| {
+            brush.normalized_distance(pos - center, axis) <= 1.
+        })
+    }
+
+    /// Copies the voxels within bounds for which inside(position) is true, voxels outside of the
+    /// field of the entity being left out too
+    fn copy(
+        entity: &ProceduralEntity,
+        (min, max): (ICoords, ICoords),
+        center: Vec3,
+        axis: Vec3,
+        inside: impl Fn(Vec3) -> bool,
+    ) -> Self {
+        let extent = FieldExtent::new(
+            (max.x - min.x + 1).max(0) as usize,
+            (max.y - min.y + 1).max(0) as usize,
+            (max.z - min.z + 1).max(0) as usize,
+        );
+        let voxels = (0..extent.volume())
+            .map(|i| {
+                let c = extent.coords(i);
+                let (x, y, z) = (
+                    min.x as i64 + c.x as i64,
+                    min.y as i64 + c.y as i64,
+                    min.z as i64 + c.z as i64,
+                );
+                let pos = Vec3::new(x as f32, y as f32, z as f32);
+                if !entity.extent.contains(x, y, z) || !inside(pos) {
+                    return None;
+                }
+                Some(entity.voxel_field[entity.extent.index(x as usize, y as usize, z as usize)])
+            })
+            .collect();
+        Self {
+            extent,
+            voxels,
+            origin: center - Vec3::new(min.x as f32, min.y as f32, min.z as f32),
+            axis: axis.normalize_or(Vec3::NEG_Y),
+        }
+    }
+
+    /// Bounding box (included) of the voxels of a field covered by the clip when pasted with its
+    /// origin on `position`, rotated by rotation
+    pub fn bounds(&self, position: Vec3, rotation: Quat) -> (ICoords, ICoords) {
+        let size = Vec3::new(
+            self.extent.x as f32,
+            self.extent.y as f32,
+            self.extent.z as f32,
+        ) - Vec3::ONE;
+        let (mut min, mut max) = (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN));
+        for corner in 0..8 {
+            let c = Vec3::new(
+                (corner & 1) as f32,
+                ((corner >> 1) & 1) as f32,
+                ((corner >> 2) & 1) as f32,
+            );
+            let pos = position + rotation * (c * size - self.origin);
+            min = min.min(pos);
+            max = max.max(pos);
+        }
+        let (min, max) = (min.floor().as_ivec3(), max.ceil().as_ivec3());
+        (
+            ICoords {
+                x: min.x,
+                y: min.y,
+                z: min.z,
+            },
+            ICoords {
+                x: max.x,
+                y: max.y,
+                z: max.z,
+            },
+        )
+    }
+
+    /// Voxel of the clip at a position (in voxels of the clip), trilinearly interpolated between
+    /// the copied voxels, None outside of the copied shape
+    /// Positions falling on a voxel (as when pasting with 90 degree turns) give it unchanged
+    pub fn sample(&self, pos: Vec3) -> Option<Voxel> {
+        let rounded = pos.round();
+        let pos = if (pos - rounded).abs().max_element() < 1e-3 {
+            rounded
+        } else {
+            pos
+        };
+        let base = pos.floor();
+        let f = pos - base;
+
+        let (mut value, mut total) = (0., 0.);
+        // material of the copied voxel with the largest weight
+        let (mut material, mut largest) = (None, 0.);
+        for corner in 0..8 {
+            let offset = IVec3::new(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let weight = (if offset.x == 1 { f.x } else { 1. - f.x })
+                * (if offset.y == 1 { f.y } else { 1. - f.y })
+                * (if offset.z == 1 { f.z } else { 1. - f.z });
+            if weight <= 0. {
+                continue;
+            }
+            let c = base.as_ivec3() + offset;
+            if !self.extent.contains(c.x as i64, c.y as i64, c.z as i64) {
+                continue;
+            }
+            let Some(voxel) =
+                self.voxels[self.extent.index(c.x as usize, c.y as usize, c.z as usize)]
+            else {
+                continue;
+            };
+            value += weight * voxel.value;
+            total += weight;
+            if weight > largest {
+                (material, largest) = (Some(voxel.material), weight);
+            }
+        }
+
+        // mostly outside of the copied shape
+        if total < 0.5 {
+            return None;
+        }
+        material.map(|material| Voxel {
+            value: value / total,
+            material,
+        })
+    }
+}
+
+/// Voxels copied by the copy tool, pasted by the paste tool
+#[derive(Resource, Default)]
+pub struct VoxelClipboard {
+    pub clip: Option<VoxelClip>,
+    // turn of the pasted clip around the axis of the paste, in radians (see paste_rotation)
+    pub angle: f32,
+    pub blend: BlendMode,
+    // entity and voxel of the first corner of the box being copied by the box copy tool
+    pub box_corner: Option<(Entity, IVec3)>,
+}
+
+impl VoxelClipboard {
+    /// Rotation of the clip when pasted along axis, in the local space of the entity
+    /// When the clip is turned by a multiple of 90 degrees, the axis of the clip and the paste
+    /// axis are snapped to the nearest axes of the entity, so that the rotation is made of
+    /// quarter turns and the pasted voxels are exact copies
+    pub fn paste_rotation(&self, clip: &VoxelClip, axis: Vec3) -> Quat {
+        let axis = axis.normalize_or(clip.axis);
+        let turns = self.angle / std::f32::consts::FRAC_PI_2;
+        if (turns - turns.round()).abs() > 1e-3 {
+            return Quat::from_axis_angle(axis, self.angle)
+                * Quat::from_rotation_arc(clip.axis, axis);
+        }
+
+        let (from, to) = (nearest_axis(clip.axis), nearest_axis(axis));
+        let arc = if from == to {
+            Quat::IDENTITY
+        } else if from == -to {
+            // half turn around another axis of the entity
+            Quat::from_axis_angle(
+                nearest_axis(from.any_orthonormal_vector()),
+                std::f32::consts::PI,
+            )
+        } else {
+            Quat::from_axis_angle(from.cross(to), std::f32::consts::FRAC_PI_2)
+        };
+        Quat::from_axis_angle(to, turns.round() * std::f32::consts::FRAC_PI_2) * arc
+    }
+}
+
+/// Axis of the entity (positive or negative) closest to a direction
+fn nearest_axis(direction: Vec3) -> Vec3 {
+    let abs = direction.abs();
+    let axis = if abs.x >= abs.y && abs.x >= abs.z {
+        Vec3::X
+    } else if abs.y >= abs.z {
+        Vec3::Y
+    } else {
+        Vec3::Z
+    };
+    axis * direction.dot(axis).signum()
+}
+
+/// Changes the paste settings with the keyboard:
+/// O turns the pasted clip by 90 degrees, I by 15 degrees, P cycles through the blend modes
+pub fn clipboard_input_system(
+    mut clipboard: ResMut<VoxelClipboard>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyO) {
+        clipboard.angle = (clipboard.angle + std::f32::consts::FRAC_PI_2) % std::f32::consts::TAU;
+    } else if keyboard_input.just_pressed(KeyCode::KeyI) {
+        clipboard.angle = (clipboard.angle + 15f32.to_radians()) % std::f32::consts::TAU;
+    } else if keyboard_input.just_pressed(KeyCode::KeyP) {
+        clipboard.blend = match clipboard.blend {
+            BlendMode::Replace => BlendMode::Union,
+            BlendMode::Union => BlendMode::Subtract,
+            BlendMode::Subtract => BlendMode::Replace,
+        };
+    }
+}
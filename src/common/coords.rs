@@ -1,7 +1,9 @@
 use crate::common::constants::*;
+use crate::common::field_extent::FieldExtent;
 use ordered_float::OrderedFloat;
 
 use bevy::prelude::*;
+use std::fmt;
 use std::ops::{Add, Mul, Sub};
 
 use crate::common::coords_neighbours_iter::CoordsNeighboursIter;
@@ -34,6 +36,10 @@ pub type ChunkCoords = Coords<i64>;
 // Value should range from 0 to CHUNK_VSIZE (33)
 pub type VoxelCoords = Coords<u8>;
 
+// Coordinates used for voxel position inside the field of a ProceduralEntity
+// Entity fields are not bound to the chunk size, so they need more than a u8
+pub type LocalVoxelCoords = Coords<u32>;
+
 // Signed Coords with no predefined context
 pub type ICoords = Coords<i32>;
 
@@ -94,6 +100,43 @@ impl ChunkCoords {
     }
 }
 
+/// Error returned when a point does not fall inside the voxel field of an entity
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutOfFieldError {
+    pub point: Vec3,
+    pub extent: FieldExtent,
+}
+
+impl fmt::Display for OutOfFieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "point {} is outside of the voxel field ({:?})",
+            self.point, self.extent
+        )
+    }
+}
+
+impl std::error::Error for OutOfFieldError {}
+
+impl LocalVoxelCoords {
+    pub fn new(x: u32, y: u32, z: u32) -> Self {
+        Self { x, y, z }
+    }
+
+    /// Returns the voxel nearest to a point expressed in the local space of an entity,
+    /// fails if that voxel is not part of the field
+    pub fn from_local_point(point: Vec3, extent: FieldExtent) -> Result<Self, OutOfFieldError> {
+        let rounded = point.round();
+        if !rounded.is_finite()
+            || !extent.contains(rounded.x as i64, rounded.y as i64, rounded.z as i64)
+        {
+            return Err(OutOfFieldError { point, extent });
+        }
+        Ok(Self::new(rounded.x as u32, rounded.y as u32, rounded.z as u32))
+    }
+}
+
 impl<T: Add<Output = T>> Add for Coords<T> {
     type Output = Self;
 
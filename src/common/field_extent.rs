@@ -0,0 +1,50 @@
+use crate::common::coords::*;
+
+/// Number of voxels along each axis of a voxel field
+/// Voxels are stored x-major: z varies the fastest, then y, then x
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FieldExtent {
+    pub x: usize,
+    pub y: usize,
+    pub z: usize,
+}
+
+impl FieldExtent {
+    pub fn new(x: usize, y: usize, z: usize) -> Self {
+        Self { x, y, z }
+    }
+
+    /// Extent with the same number of voxels along every axis
+    pub fn cube(size: usize) -> Self {
+        Self::new(size, size, size)
+    }
+
+    /// Total number of voxels in the field
+    pub fn volume(&self) -> usize {
+        self.x * self.y * self.z
+    }
+
+    /// Linearize coordinates inside the field into the corresponding index
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        z + y * self.z + x * self.y * self.z
+    }
+
+    /// Delinearize an index into coordinates inside the field
+    pub fn coords(&self, index: usize) -> Coords<usize> {
+        Coords {
+            x: index / (self.y * self.z),
+            y: (index / self.z) % self.y,
+            z: index % self.z,
+        }
+    }
+
+    /// Returns true if the (possibly negative) coordinates lie inside the field
+    pub fn contains(&self, x: i64, y: i64, z: i64) -> bool {
+        x >= 0
+            && y >= 0
+            && z >= 0
+            && (x as usize) < self.x
+            && (y as usize) < self.y
+            && (z as usize) < self.z
+    }
+}
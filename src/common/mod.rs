@@ -1,9 +1,9 @@
 pub mod constants;
 pub mod coords;
-pub mod coords_iterators;
-pub mod coords_neighbours;
 pub mod coords_neighbours_iter;
+pub mod field_extent;
 pub mod math;
+pub mod region;
 pub mod vertex;
 pub mod voxel_material;
 pub mod voxels;
@@ -0,0 +1,24 @@
+use crate::common::coords::*;
+
+/// Axis aligned box of cubes inside a voxel field
+/// `min` is inclusive and `max` is exclusive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub min: LocalVoxelCoords,
+    pub max: LocalVoxelCoords,
+}
+
+impl Region {
+    pub fn new(min: LocalVoxelCoords, max: LocalVoxelCoords) -> Self {
+        Self { min, max }
+    }
+
+    pub fn intersects(&self, other: &Region) -> bool {
+        self.min.x < other.max.x
+            && other.min.x < self.max.x
+            && self.min.y < other.max.y
+            && other.min.y < self.max.y
+            && self.min.z < other.max.z
+            && other.min.z < self.max.z
+    }
+}
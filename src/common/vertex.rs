@@ -10,4 +10,6 @@ pub struct Vertex {
     pub normal: Vec3,
     pub color: Color,
     pub voxel_material: VoxelMaterial,
+    // baked ambient occlusion, the fraction of ambient light reaching the vertex
+    pub occlusion: f32,
 }
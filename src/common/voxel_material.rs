@@ -1,6 +1,144 @@
-#[derive(Clone, Copy, PartialEq, Eq)]
-pub enum VoxelMaterial {
-    AIR,
-    STONE,
-    DIRT,
+use bevy::prelude::*;
+
+/// Material of a voxel, an index into the MaterialRegistry
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct VoxelMaterial(pub u16);
+
+impl VoxelMaterial {
+    pub const AIR: VoxelMaterial = VoxelMaterial(0);
+    pub const STONE: VoxelMaterial = VoxelMaterial(1);
+    pub const DIRT: VoxelMaterial = VoxelMaterial(2);
+}
+
+/// Physical properties of a material
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MaterialProperties {
+    // fraction of each carve or fill resisted by a voxel, between 0 (changes at once) and 1
+    // (cannot be dug into)
+    pub hardness: f32,
+    // fraction of each smooth, flatten or smear resisted by a voxel, between 0 and 1
+    pub toughness: f32,
+    // mass of a voxel of the material
+    pub density: f32,
+}
+
+impl Default for MaterialProperties {
+    fn default() -> Self {
+        Self {
+            hardness: 0.0,
+            toughness: 0.0,
+            density: 1.0,
+        }
+    }
+}
+
+/// Everything known about a material
+#[derive(Clone, PartialEq, Debug)]
+pub struct MaterialDefinition {
+    pub name: String,
+    // colour of the vertices of the material
+    pub color: Color,
+    pub properties: MaterialProperties,
+}
+
+/// All the materials voxels can be made of, VoxelMaterial(i) being the i-th one
+/// AIR, STONE and DIRT are always registered first, other materials are added with register
+#[derive(Resource, Clone)]
+pub struct MaterialRegistry {
+    materials: Vec<MaterialDefinition>,
+    // used for materials missing from the registry
+    pub fallback: Color,
+}
+
+impl MaterialRegistry {
+    /// Adds a material, returning the VoxelMaterial to use for its voxels
+    pub fn register(
+        &mut self,
+        name: &str,
+        color: Color,
+        properties: MaterialProperties,
+    ) -> VoxelMaterial {
+        self.materials.push(MaterialDefinition {
+            name: name.to_string(),
+            color,
+            properties,
+        });
+        VoxelMaterial(self.materials.len() as u16 - 1)
+    }
+
+    pub fn get(&self, material: VoxelMaterial) -> Option<&MaterialDefinition> {
+        self.materials.get(material.0 as usize)
+    }
+
+    /// Every registered material, AIR included
+    pub fn materials(&self) -> impl Iterator<Item = (VoxelMaterial, &MaterialDefinition)> {
+        self.materials
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (VoxelMaterial(i as u16), m))
+    }
+
+    pub fn color(&self, material: VoxelMaterial) -> Color {
+        self.get(material).map_or(self.fallback, |m| m.color)
+    }
+
+    pub fn properties(&self, material: VoxelMaterial) -> MaterialProperties {
+        self.get(material)
+            .map_or(MaterialProperties::default(), |m| m.properties)
+    }
+}
+
+impl Default for MaterialRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            materials: Vec::new(),
+            fallback: Color::linear_rgb(0.6, 0.5, 0.2),
+        };
+        registry.register(
+            "air",
+            Color::linear_rgb(0.6, 0.5, 0.2),
+            MaterialProperties {
+                hardness: 0.0,
+                toughness: 0.0,
+                density: 0.0,
+            },
+        );
+        registry.register(
+            "stone",
+            Color::linear_rgb(0.4, 0.4, 0.4),
+            MaterialProperties {
+                hardness: 0.6,
+                toughness: 0.5,
+                density: 2.6,
+            },
+        );
+        registry.register(
+            "dirt",
+            Color::linear_rgb(0.6, 0.5, 0.2),
+            MaterialProperties {
+                hardness: 0.1,
+                toughness: 0.1,
+                density: 1.5,
+            },
+        );
+        registry.register(
+            "sand",
+            Color::linear_rgb(0.76, 0.7, 0.5),
+            MaterialProperties {
+                hardness: 0.0,
+                toughness: 0.0,
+                density: 1.6,
+            },
+        );
+        registry.register(
+            "ice",
+            Color::linear_rgb(0.7, 0.85, 0.95),
+            MaterialProperties {
+                hardness: 0.3,
+                toughness: 0.7,
+                density: 0.9,
+            },
+        );
+        registry
+    }
 }
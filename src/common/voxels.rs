@@ -1,6 +1,6 @@
 use crate::common::voxel_material::VoxelMaterial;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub struct Voxel {
     pub value: f32,
     pub material: VoxelMaterial,
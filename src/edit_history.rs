@@ -0,0 +1,767 @@
+use avian3d::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+
+use crate::common::coords::{Coords, ICoords};
+use crate::common::field_extent::FieldExtent;
+use crate::common::voxel_material::{MaterialRegistry, VoxelMaterial};
+use crate::common::voxels::Voxel;
+use crate::entity_mesh::EntityMeshComponent;
+use crate::procedural_entity::{ProceduralEntity, DENSITY_BAND};
+use crate::resources::{LastEditReport, ProcEntities};
+
+/// Unchanged voxels between two changed ones up to which both go in the same span (a new span
+/// costs about as much as a few voxels)
+const SPAN_GAP: usize = 4;
+
+/// Voxel of the parts of a field added by an edit
+const EMPTY_VOXEL: Voxel = Voxel {
+    value: -DENSITY_BAND,
+    material: VoxelMaterial::AIR,
+};
+
+/// Run of consecutive voxels (in the layout of a field) changed by an edit
+#[derive(Clone)]
+struct VoxelSpan {
+    start: usize,
+    before: Vec<Voxel>,
+    after: Vec<Voxel>,
+}
+
+/// Motion of a spawned entity
+type Motion = (Transform, LinearVelocity, AngularVelocity);
+
+/// Voxels of an entity that an edit can change, copied before the edit
+pub struct EditSnapshot {
+    // extent of the whole field
+    extent: FieldExtent,
+    min: Coords<usize>,
+    size: FieldExtent,
+    voxels: Vec<Voxel>,
+}
+
+impl EditSnapshot {
+    /// Copies the voxels of an entity within the bounding box of all the bounds (min and max
+    /// included), the ones outside of its field being empty
+    pub fn new(
+        entity: &ProceduralEntity,
+        bounds: impl IntoIterator<Item = (ICoords, ICoords)>,
+    ) -> Self {
+        let (min, max) = bounds
+            .into_iter()
+            .reduce(|(min, max), (other_min, other_max)| {
+                (
+                    ICoords {
+                        x: min.x.min(other_min.x),
+                        y: min.y.min(other_min.y),
+                        z: min.z.min(other_min.z),
+                    },
+                    ICoords {
+                        x: max.x.max(other_max.x),
+                        y: max.y.max(other_max.y),
+                        z: max.z.max(other_max.z),
+                    },
+                )
+            })
+            .unwrap_or_default();
+        let min = Coords {
+            x: min.x.max(0) as usize,
+            y: min.y.max(0) as usize,
+            z: min.z.max(0) as usize,
+        };
+        let size = FieldExtent::new(
+            (max.x + 1).max(min.x as i32) as usize - min.x,
+            (max.y + 1).max(min.y as i32) as usize - min.y,
+            (max.z + 1).max(min.z as i32) as usize - min.z,
+        );
+        let voxels = (0..size.volume())
+            .map(|i| {
+                let c = size.coords(i);
+                voxel_at(
+                    entity.extent,
+                    &entity.voxel_field,
+                    Coords {
+                        x: min.x + c.x,
+                        y: min.y + c.y,
+                        z: min.z + c.z,
+                    },
+                )
+            })
+            .collect();
+        Self {
+            extent: entity.extent,
+            min,
+            size,
+            voxels,
+        }
+    }
+
+    /// Voxel at c before the edit, None if it is not part of the snapshot
+    fn get(&self, c: Coords<usize>) -> Option<Voxel> {
+        let (x, y, z) = (
+            c.x.checked_sub(self.min.x)?,
+            c.y.checked_sub(self.min.y)?,
+            c.z.checked_sub(self.min.z)?,
+        );
+        if x < self.size.x && y < self.size.y && z < self.size.z {
+            Some(self.voxels[self.size.index(x, y, z)])
+        } else {
+            None
+        }
+    }
+}
+
+/// One edit of an entity, stored as the spans of voxels it changed
+/// The edited field is the field left by the edit before its regions were extracted (see
+/// ProceduralEntity::extract_regions): merging the fields of the entities it left gives it back,
+/// but for the voxels they dropped (the regions too small to be kept, and the voxels cropped
+/// away), which are kept as well
+struct HistoryEntry {
+    // entity the edit was applied to
+    edited: Entity,
+    // transform of the edited entity, for the edits that left nothing
+    transform: Transform,
+    // entities left by the edit, with the offset and extent of their field in the edited one
+    // (see ProceduralEntity::crop_field)
+    fragments: Vec<(Entity, Coords<usize>, FieldExtent)>,
+    before_extent: FieldExtent,
+    after_extent: FieldExtent,
+    spans: Vec<VoxelSpan>,
+    // runs of voxels of the edited field dropped by its fragments, by their first index
+    dropped: Vec<(usize, Vec<Voxel>)>,
+}
+
+impl HistoryEntry {
+    /// None if the edit did not change any voxel
+    fn new(
+        edited: Entity,
+        transform: Transform,
+        before: &EditSnapshot,
+        after: &ProceduralEntity,
+        fragments: &[(Entity, &ProceduralEntity, Coords<usize>)],
+    ) -> Option<Self> {
+        let after_extent = after.extent;
+        let before_at = |i: usize| {
+            before
+                .get(after_extent.coords(i))
+                .unwrap_or(after.voxel_field[i])
+        };
+
+        // only the voxels of the snapshot can have changed
+        let indices = (0..before.size.volume()).filter_map(|i| {
+            let c = before.size.coords(i);
+            let (x, y, z) = (before.min.x + c.x, before.min.y + c.y, before.min.z + c.z);
+            (x < after_extent.x && y < after_extent.y && z < after_extent.z)
+                .then(|| after_extent.index(x, y, z))
+        });
+        let spans: Vec<_> = runs(indices, |i| before_at(i) != after.voxel_field[i])
+            .into_iter()
+            .map(|(start, end)| VoxelSpan {
+                start,
+                before: (start..end).map(before_at).collect(),
+                after: after.voxel_field[start..end].to_vec(),
+            })
+            .collect();
+        if spans.is_empty() {
+            return None;
+        }
+
+        // the fragments only drop voxels when the edit split the entity, dropped a small region
+        // or the fields got cropped
+        let kept_whole = match fragments {
+            [(_, fragment, offset)] => {
+                *offset == Coords::default()
+                    && fragment.extent == after_extent
+                    && fragment.voxel_field == after.voxel_field
+            }
+            _ => false,
+        };
+        let mut dropped = Vec::new();
+        if !kept_whole {
+            let merged = merge_fragments(
+                after_extent,
+                fragments.iter().map(|(_, e, offset)| (*e, *offset)),
+            );
+            dropped = runs(0..after_extent.volume(), |i| {
+                merged[i] != after.voxel_field[i]
+            })
+            .into_iter()
+            .map(|(start, end)| (start, after.voxel_field[start..end].to_vec()))
+            .collect();
+        }
+
+        Some(Self {
+            edited,
+            transform,
+            fragments: fragments
+                .iter()
+                .map(|(id, e, offset)| (*id, *offset, e.extent))
+                .collect(),
+            before_extent: before.extent,
+            after_extent,
+            spans,
+            dropped,
+        })
+    }
+
+    /// Approximate memory used by the entry, in bytes
+    fn size(&self) -> usize {
+        let span_size = |span: &VoxelSpan| {
+            std::mem::size_of::<VoxelSpan>()
+                + (span.before.len() + span.after.len()) * std::mem::size_of::<Voxel>()
+        };
+        let dropped_size = |(_, voxels): &(usize, Vec<Voxel>)| {
+            std::mem::size_of::<(usize, Vec<Voxel>)>() + voxels.len() * std::mem::size_of::<Voxel>()
+        };
+        std::mem::size_of::<Self>()
+            + self.fragments.len() * std::mem::size_of::<(Entity, Coords<usize>, FieldExtent)>()
+            + self.spans.iter().map(span_size).sum::<usize>()
+            + self.dropped.iter().map(dropped_size).sum::<usize>()
+    }
+
+    /// Field of the edited entity before the edit, from the fields of the entities it left
+    fn field_before(&self, fragments: &[(&ProceduralEntity, Coords<usize>)]) -> Vec<Voxel> {
+        let mut field = merge_fragments(self.after_extent, fragments.iter().copied());
+        for (start, voxels) in self.dropped.iter() {
+            field[*start..*start + voxels.len()].copy_from_slice(voxels);
+        }
+        for span in self.spans.iter() {
+            field[span.start..span.start + span.before.len()].copy_from_slice(&span.before);
+        }
+        (0..self.before_extent.volume())
+            .map(|i| voxel_at(self.after_extent, &field, self.before_extent.coords(i)))
+            .collect()
+    }
+
+    /// Edited field left by the edit, from the field before it
+    fn field_after(&self, before: &[Voxel]) -> Vec<Voxel> {
+        let mut field: Vec<Voxel> = (0..self.after_extent.volume())
+            .map(|i| voxel_at(self.before_extent, before, self.after_extent.coords(i)))
+            .collect();
+        for span in self.spans.iter() {
+            field[span.start..span.start + span.after.len()].copy_from_slice(&span.after);
+        }
+        field
+    }
+
+    /// The edited entity as it was before the edit, from the entities it left (in the order of
+    /// the fragments)
+    fn undo(&self, fragments: Vec<(ProceduralEntity, Motion)>) -> (ProceduralEntity, Motion) {
+        let field = self.field_before(
+            &fragments
+                .iter()
+                .zip(self.fragments.iter())
+                .map(|((e, _), (_, offset, _))| (e, *offset))
+                .collect::<Vec<_>>(),
+        );
+        let first = fragments.into_iter().zip(self.fragments.iter()).next();
+        let (mut restored, motion) = match first {
+            // settings of the first fragment, its symmetry centre moved back to the edited field,
+            // the edited field being placed where it was in the first fragment
+            Some(((mut restored, (mut t, lv, av)), (_, offset, _))) => {
+                let offset = Vec3::new(offset.x as f32, offset.y as f32, offset.z as f32);
+                restored.symmetry.center += offset;
+                t.translation -= t.rotation * (offset * t.scale);
+                (restored, (t, lv, av))
+            }
+            None => (
+                ProceduralEntity::new(self.before_extent),
+                (
+                    self.transform,
+                    LinearVelocity::default(),
+                    AngularVelocity::default(),
+                ),
+            ),
+        };
+        restored.replace_field(self.before_extent, field);
+        (restored, motion)
+    }
+
+    /// The entities left by the edit (in the order of the fragments), from the edited entity as
+    /// it was before it
+    fn redo(
+        &self,
+        (mut edited, (t, lv, av)): (ProceduralEntity, Motion),
+    ) -> Result<Vec<(ProceduralEntity, Motion)>, &'static str> {
+        if edited.extent != self.before_extent {
+            return Err("the edited entity changed");
+        }
+        let field = self.field_after(&edited.voxel_field);
+        edited.replace_field(self.after_extent, field);
+        let mut regions = edited.extract_regions();
+        if regions.len() != self.fragments.len() {
+            return Err("the edited entity changed");
+        }
+        // fields cropped and placed like the ones the edit left
+        Ok(regions
+            .drain(..)
+            .zip(self.fragments.iter())
+            .map(|(mut region, (_, offset, extent))| {
+                if *offset != Coords::default() || *extent != region.extent {
+                    region.crop_field(*offset, *extent);
+                    region.modification_count = 0;
+                }
+                let offset = Vec3::new(offset.x as f32, offset.y as f32, offset.z as f32);
+                let mut t = t;
+                t.translation += t.rotation * (offset * t.scale);
+                (region, (t, lv, av))
+            })
+            .collect())
+    }
+
+    fn replace_entity(&mut self, old: Entity, new: Entity) {
+        if self.edited == old {
+            self.edited = new;
+        }
+        for (id, _, _) in self.fragments.iter_mut() {
+            if *id == old {
+                *id = new;
+            }
+        }
+    }
+}
+
+/// Runs of consecutive indices (start included, end excluded) for which changed is true, out of
+/// increasing indices, runs less than SPAN_GAP apart being merged
+fn runs(
+    indices: impl Iterator<Item = usize>,
+    changed: impl Fn(usize) -> bool,
+) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for i in indices.filter(|&i| changed(i)) {
+        match runs.last_mut() {
+            Some((_, end)) if i - *end <= SPAN_GAP => *end = i + 1,
+            _ => runs.push((i, i + 1)),
+        }
+    }
+    runs
+}
+
+/// Voxel at c in a field, empty outside of it
+fn voxel_at(extent: FieldExtent, field: &[Voxel], c: Coords<usize>) -> Voxel {
+    if c.x < extent.x && c.y < extent.y && c.z < extent.z {
+        field[extent.index(c.x, c.y, c.z)]
+    } else {
+        EMPTY_VOXEL
+    }
+}
+
+/// Field of the given extent holding the voxels of all the fragments, placed at their offset
+/// Where fragments overlap, the most solid voxel is kept (the solid voxels of a fragment are
+/// empty in the others), the voxels of the fragments replacing the empty ones around them
+fn merge_fragments<'a>(
+    extent: FieldExtent,
+    fragments: impl Iterator<Item = (&'a ProceduralEntity, Coords<usize>)>,
+) -> Vec<Voxel> {
+    let mut merged = vec![EMPTY_VOXEL; extent.volume()];
+    for (fragment, offset) in fragments {
+        for (i, voxel) in fragment.voxel_field.iter().enumerate() {
+            let c = fragment.extent.coords(i);
+            let (x, y, z) = (c.x + offset.x, c.y + offset.y, c.z + offset.z);
+            if !extent.contains(x as i64, y as i64, z as i64) {
+                continue;
+            }
+            let merged_voxel = &mut merged[extent.index(x, y, z)];
+            if voxel.value >= merged_voxel.value {
+                *merged_voxel = *voxel;
+            }
+        }
+    }
+    merged
+}
+
+/// Edits of the procedural entities that can be undone and redone
+/// Only the voxels changed by each edit are kept, the oldest edits being forgotten when the
+/// history takes more than its memory budget
+/// The edits of a stroke (from the moment the brush touches an entity to the moment it leaves
+/// it) are undone and redone together, as one operation
+#[derive(Resource)]
+pub struct EditHistory {
+    undo: VecDeque<Vec<HistoryEntry>>,
+    redo: Vec<Vec<HistoryEntry>>,
+    // whether the next edit belongs to the last operation
+    stroke_open: bool,
+    // in bytes
+    pub budget: usize,
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            stroke_open: false,
+            budget: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl EditHistory {
+    /// Records an edit of `edited`, given its transform, the snapshot of its voxels taken before
+    /// the edit, the entity as the edit left it, and the entities extracted from it, with the
+    /// offset of their field in its field (see ProceduralEntity::minimize_field_size)
+    /// Edits that did not change any voxel are ignored, the edits that were undone are forgotten
+    /// The edit joins the operation of the previous one until the stroke ends
+    pub fn record(
+        &mut self,
+        edited: Entity,
+        transform: Transform,
+        before: &EditSnapshot,
+        after: &ProceduralEntity,
+        fragments: &[(Entity, &ProceduralEntity, Coords<usize>)],
+    ) {
+        let Some(entry) = HistoryEntry::new(edited, transform, before, after, fragments) else {
+            return;
+        };
+        self.redo.clear();
+        match self.undo.back_mut() {
+            Some(operation) if self.stroke_open => operation.push(entry),
+            _ => self.undo.push_back(vec![entry]),
+        }
+        self.stroke_open = true;
+        while self.memory_usage() > self.budget && self.undo.pop_front().is_some() {}
+    }
+
+    /// The next edit starts a new operation
+    pub fn end_stroke(&mut self) {
+        self.stroke_open = false;
+    }
+
+    /// Approximate memory used by the history, in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.undo
+            .iter()
+            .chain(self.redo.iter())
+            .flatten()
+            .map(|entry| entry.size())
+            .sum()
+    }
+
+    /// Entities get respawned by undo and redo, the edits referring to them are updated
+    fn replace_entity(&mut self, old: Entity, new: Entity) {
+        for entry in self.undo.iter_mut().chain(self.redo.iter_mut()).flatten() {
+            entry.replace_entity(old, new);
+        }
+    }
+}
+
+/// Entities of an operation being undone or redone, by the ids they had when it was recorded
+/// The entities made by an edit of the operation are only spawned if no later edit uses them
+#[derive(Default)]
+struct Workspace {
+    made: HashMap<Entity, (ProceduralEntity, Motion)>,
+    // spawned entities used by the operation, despawned once it is done
+    used: Vec<Entity>,
+}
+
+impl Workspace {
+    /// Takes an entity made by the operation, or a copy of a spawned one, None if it is gone
+    fn take(
+        &mut self,
+        id: Entity,
+        proc_entities: &ProcEntities,
+        motion_of: impl Fn(Entity) -> Option<Motion>,
+    ) -> Option<(ProceduralEntity, Motion)> {
+        if let Some(made) = self.made.remove(&id) {
+            return Some(made);
+        }
+        if self.used.contains(&id) {
+            return None;
+        }
+        let motion = motion_of(id)?;
+        let entity = proc_entities.0.get(&id)?.lock().unwrap().clone();
+        self.used.push(id);
+        Some((entity, motion))
+    }
+}
+
+/// Ctrl+Z undoes the last operation, Ctrl+Y (or Ctrl+Shift+Z) redoes it
+/// The entities left by an edit are despawned and replaced by the edited entity as it was
+/// (merging back the fragments of an edit that split it), and the other way round
+/// Nothing changes if an entity of the operation is gone or changed, the last edit window of the
+/// UI tells why
+pub fn edit_history_system(
+    mut history: ResMut<EditHistory>,
+    mut proc_entities: ResMut<ProcEntities>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    registry: Res<MaterialRegistry>,
+    transform_q: Query<(&Transform, &LinearVelocity, &AngularVelocity)>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut last_report: ResMut<LastEditReport>,
+) {
+    if !keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    let shift = keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let undo = keyboard_input.just_pressed(KeyCode::KeyZ) && !shift;
+    let redo = keyboard_input.just_pressed(KeyCode::KeyY)
+        || (keyboard_input.just_pressed(KeyCode::KeyZ) && shift);
+
+    let operation = if undo {
+        history.undo.pop_back()
+    } else if redo {
+        history.redo.pop()
+    } else {
+        None
+    };
+    let Some(operation) = operation else {
+        return;
+    };
+    history.end_stroke();
+
+    let motion_of = |id: Entity| transform_q.get(id).ok().map(|(t, lv, av)| (*t, *lv, *av));
+    let mut workspace = Workspace::default();
+    let done: Result<(), &str> = if undo {
+        operation.iter().rev().try_for_each(|entry| {
+            let fragments = entry
+                .fragments
+                .iter()
+                .map(|(id, _, _)| workspace.take(*id, &proc_entities, motion_of))
+                .collect::<Option<Vec<_>>>()
+                .ok_or("an entity of the edit is gone")?;
+            workspace.made.insert(entry.edited, entry.undo(fragments));
+            Ok(())
+        })
+    } else {
+        operation.iter().try_for_each(|entry| {
+            let edited = workspace
+                .take(entry.edited, &proc_entities, motion_of)
+                .ok_or("the edited entity is gone")?;
+            for ((id, _, _), fragment) in entry.fragments.iter().zip(entry.redo(edited)?) {
+                workspace.made.insert(*id, fragment);
+            }
+            Ok(())
+        })
+    };
+    if let Err(err) = done {
+        let action = if undo { "undo" } else { "redo" };
+        last_report.0 = Some(Err(format!("cannot {}: {}", action, err)));
+        if undo {
+            history.undo.push_back(operation);
+        } else {
+            history.redo.push(operation);
+        }
+        return;
+    }
+
+    for id in workspace.used {
+        commands.entity(id).despawn_recursive();
+        proc_entities.0.remove(&id);
+    }
+    if undo {
+        history.redo.push(operation);
+    } else {
+        history.undo.push_back(operation);
+    }
+    for (old, (mut entity, (transform, lv, av))) in workspace.made {
+        entity.generate_vertices();
+        let entity = Arc::new(Mutex::new(entity));
+        let id = EntityMeshComponent::respawn(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            &registry,
+            Arc::clone(&entity),
+            transform,
+            lv,
+            av,
+        );
+        proc_entities.0.insert(id, entity);
+        history.replace_entity(old, id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brush::{Brush, Falloff};
+    use crate::sdf::SdfNode;
+
+    /// Motion of an entity whose field is at offset in the field of an entity at the origin
+    fn motion(offset: Coords<usize>) -> Motion {
+        (
+            Transform::from_xyz(offset.x as f32, offset.y as f32, offset.z as f32),
+            LinearVelocity::default(),
+            AngularVelocity::default(),
+        )
+    }
+
+    /// Bar along x, the brush being in its middle
+    fn bar() -> (ProceduralEntity, Vec3) {
+        let center = Vec3::new(16., 6., 6.);
+        let bar = SdfNode::cuboid(center, Vec3::new(11., 2.5, 2.5));
+        (
+            ProceduralEntity::from_sdf(&bar, FieldExtent::new(32, 12, 12)),
+            center,
+        )
+    }
+
+    /// Records an edit of `edited` like entity_deform, the entities it left being cropped when
+    /// there are several of them, and returns them with the ids they get
+    fn record_edit(
+        history: &mut EditHistory,
+        edited: Entity,
+        entity: &mut ProceduralEntity,
+        edit: impl FnOnce(&mut ProceduralEntity) -> Vec<ProceduralEntity>,
+        bounds: (ICoords, ICoords),
+    ) -> Vec<(Entity, ProceduralEntity, Coords<usize>)> {
+        let before = EditSnapshot::new(entity, [bounds]);
+        let mut regions = edit(entity);
+        let split = regions.len() > 1;
+        let fragments: Vec<_> = regions
+            .drain(..)
+            .enumerate()
+            .map(|(i, mut region)| {
+                let offset = if split {
+                    region.minimize_field_size()
+                } else {
+                    Coords::default()
+                };
+                let id = Entity::from_raw(edited.index() + i as u32 + 1);
+                (id, region, offset)
+            })
+            .collect();
+        history.record(
+            edited,
+            Transform::default(),
+            &before,
+            entity,
+            &fragments
+                .iter()
+                .map(|(id, region, offset)| (*id, region, *offset))
+                .collect::<Vec<_>>(),
+        );
+        fragments
+    }
+
+    #[test]
+    fn undoing_a_split_gives_back_the_original_field() {
+        let (mut entity, center) = bar();
+        let original = entity.clone();
+        let registry = MaterialRegistry::default();
+        let brush = Brush {
+            radius: 4.,
+            strength: 1.,
+            falloff: Falloff::Constant,
+            ..default()
+        };
+
+        // carves the middle of the bar until it splits, as one stroke (the materials resist)
+        let mut history = EditHistory::default();
+        let mut edited = Entity::from_raw(1);
+        let mut fragments = Vec::new();
+        for _ in 0..20 {
+            fragments = record_edit(
+                &mut history,
+                edited,
+                &mut entity,
+                |entity| entity.carve(center, Vec3::Y, &brush, &registry).unwrap().0,
+                ProceduralEntity::brush_bounds(center, &brush),
+            );
+            if fragments.len() > 1 {
+                break;
+            }
+            let (id, region, _) = fragments.pop().unwrap();
+            (edited, entity) = (id, region);
+        }
+        assert_eq!(fragments.len(), 2, "the bar did not split");
+        assert_eq!(history.undo.len(), 1);
+        assert!(fragments
+            .iter()
+            .all(|(_, region, _)| region.extent != original.extent));
+
+        // undo of the stroke
+        let operation = &history.undo[0];
+        let mut entities: Vec<_> = fragments
+            .iter()
+            .map(|(_, region, offset)| (region.clone(), motion(*offset)))
+            .collect();
+        for entry in operation.iter().rev() {
+            entities = vec![entry.undo(entities)];
+        }
+        assert_eq!(entities.len(), 1);
+        let (restored, (t, _, _)) = entities.pop().unwrap();
+        assert_eq!(restored.extent, original.extent);
+        assert!(restored.voxel_field == original.voxel_field);
+        assert_eq!(t.translation, Vec3::ZERO);
+
+        // redo gives back the same fragments, cropped and placed the same way
+        let mut entities = vec![(restored, motion(Coords::default()))];
+        for entry in operation.iter() {
+            assert_eq!(entities.len(), 1);
+            entities = entry.redo(entities.pop().unwrap()).unwrap();
+        }
+        assert_eq!(entities.len(), 2);
+        for ((redone, (t, _, _)), (_, fragment, offset)) in entities.iter().zip(fragments.iter()) {
+            assert_eq!(redone.extent, fragment.extent);
+            assert!(redone.voxel_field == fragment.voxel_field);
+            assert_eq!(*t, motion(*offset).0);
+        }
+    }
+
+    #[test]
+    fn oldest_operations_are_forgotten_past_the_budget() {
+        let (mut entity, center) = bar();
+        let mut history = EditHistory::default();
+        let mut edited = Entity::from_raw(1);
+        // one voxel changed by each operation, along the bar
+        let mut edit = |history: &mut EditHistory, x: usize| {
+            let c = Coords {
+                x,
+                y: center.y as usize,
+                z: center.z as usize,
+            };
+            let bounds = (
+                ICoords {
+                    x: x as i32,
+                    y: c.y as i32,
+                    z: c.z as i32,
+                },
+                ICoords {
+                    x: x as i32,
+                    y: c.y as i32,
+                    z: c.z as i32,
+                },
+            );
+            let mut fragments = record_edit(
+                history,
+                edited,
+                &mut entity,
+                |entity| {
+                    let i = entity.extent.index(c.x, c.y, c.z);
+                    entity.voxel_field[i].value = 0.5;
+                    entity.extract_regions()
+                },
+                bounds,
+            );
+            history.end_stroke();
+            let (id, region, _) = fragments.pop().unwrap();
+            (edited, entity) = (id, region);
+        };
+
+        edit(&mut history, 8);
+        let operation_size = history.memory_usage();
+        assert!(operation_size > 0);
+        history.budget = 3 * operation_size;
+        for x in 9..16 {
+            edit(&mut history, x);
+            assert!(history.memory_usage() <= history.budget);
+        }
+        // only the last three operations are left
+        assert_eq!(history.undo.len(), 3);
+        assert_eq!(history.memory_usage(), 3 * operation_size);
+        let last_edited = history
+            .undo
+            .iter()
+            .map(|operation| operation[0].edited)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            last_edited,
+            (6..=8).map(Entity::from_raw).collect::<Vec<_>>()
+        );
+    }
+}
@@ -3,8 +3,16 @@ use std::sync::{Arc, Mutex};
 
 use bevy::prelude::*;
 
+use crate::brush::ActiveBrush;
+use crate::clipboard::{VoxelClip, VoxelClipboard};
+use crate::common::coords::{Coords, ICoords};
+use crate::common::voxel_material::MaterialRegistry;
+use crate::edit_history::{EditHistory, EditSnapshot};
 use crate::entity_mesh::EntityMeshComponent;
-use crate::resources::{FillMode, ProcEntities, RayMeshHits};
+use crate::procedural_entity::{EditResult, ProceduralEntity};
+use crate::resources::{
+    ActiveTool, FillMode, LastEditReport, PaintMaterial, ProcEntities, RayMeshHits, SculptTool,
+};
 
 pub fn entity_deform_system(
     mut proc_entities: ResMut<ProcEntities>,
@@ -14,9 +22,28 @@ pub fn entity_deform_system(
     mut ray_hits: ResMut<RayMeshHits>,
     transform_q: Query<(&Transform, &LinearVelocity, &AngularVelocity)>,
     fill_mode: Res<FillMode>,
+    tool: Res<ActiveTool>,
+    brush: Res<ActiveBrush>,
+    paint_material: Res<PaintMaterial>,
+    // world position of the previous hit of the current stroke, with the entities it left (the
+    // stroke restarts when the hit moves to another entity)
+    mut last_hit: Local<Option<(Vec<Entity>, Vec3)>>,
+    registry: Res<MaterialRegistry>,
+    mut history: ResMut<EditHistory>,
+    mut clipboard: ResMut<VoxelClipboard>,
+    mut last_report: ResMut<LastEditReport>,
 ) {
     // handle next ray hit (FIFO order)
     if let Some(hit) = ray_hits.0.pop_front() {
+        let previous_hit = last_hit
+            .replace((vec![hit.0], hit.1.point))
+            .filter(|(entities, _)| entities.contains(&hit.0))
+            .map(|(_, point)| point);
+        // the edits of a stroke are undone together
+        if previous_hit.is_none() {
+            history.end_stroke();
+        }
+
         // check to see if the hit is actually targeting a registered entity
         let e = if let Some(en) = proc_entities.0.get(&hit.0) {
             Arc::clone(&en)
@@ -32,35 +59,169 @@ pub fn entity_deform_system(
         let local_hit_point =
             t.rotation.inverse() * (hit.1.point - t.translation) * (1.0 / t.scale);
 
-        // fill or carve the entity and regenerate its vertices
-        let mut new_entities = {
+        // the brush radius is given in world units
+        let brush = brush.0.scaled(t.scale.max_element());
+        let local_normal = t.rotation.inverse() * hit.1.normal;
+
+        // move of the brush since the previous hit, in the entity local space
+        let stroke = previous_hit
+            .map(|previous| t.rotation.inverse() * (hit.1.point - previous) / t.scale)
+            .unwrap_or(Vec3::ZERO);
+
+        // nothing to drag on the first hit of a stroke
+        if tool.0 == SculptTool::Smear && stroke == Vec3::ZERO {
+            return;
+        }
+        // copies and pastes are made once per click
+        if matches!(
+            tool.0,
+            SculptTool::Copy | SculptTool::CopyBox | SculptTool::Paste
+        ) && previous_hit.is_some()
+        {
+            return;
+        }
+        // axis of the brush, cones point out of the surface when filling, and into it when
+        // carving, painting or copying (pastes turn the copied axis onto it)
+        let direction = match tool.0 {
+            SculptTool::Deform if fill_mode.0 => local_normal,
+            SculptTool::Deform
+            | SculptTool::Paint
+            | SculptTool::Copy
+            | SculptTool::CopyBox
+            | SculptTool::Paste => -local_normal,
+            SculptTool::Smooth | SculptTool::Flatten => local_normal,
+            SculptTool::Smear => stroke,
+        };
+        // edit made by the tool, copies leave the entity as it is
+        let apply: Box<dyn Fn(&mut ProceduralEntity) -> EditResult + '_> = match tool.0 {
+            SculptTool::Deform if fill_mode.0 => {
+                Box::new(|entity| entity.fill(local_hit_point, direction, &brush, &registry))
+            }
+            SculptTool::Deform => {
+                Box::new(|entity| entity.carve(local_hit_point, direction, &brush, &registry))
+            }
+            SculptTool::Smooth => {
+                Box::new(|entity| entity.smooth(local_hit_point, direction, &brush, &registry))
+            }
+            SculptTool::Flatten => {
+                Box::new(|entity| entity.flatten(local_hit_point, direction, &brush, &registry))
+            }
+            SculptTool::Smear => {
+                Box::new(|entity| entity.smear(local_hit_point, direction, &brush, &registry))
+            }
+            SculptTool::Paint => Box::new(|entity| {
+                entity.paint(local_hit_point, direction, &brush, paint_material.0)
+            }),
+            SculptTool::Copy => {
+                let clip =
+                    VoxelClip::from_brush(&e.lock().unwrap(), local_hit_point, direction, &brush);
+                clipboard.clip = Some(clip);
+                return;
+            }
+            // the first click sets a corner, the second one on the same entity copies the box
+            SculptTool::CopyBox => {
+                let corner = local_hit_point.round().as_ivec3();
+                let Some(first) = clipboard
+                    .box_corner
+                    .replace((hit.0, corner))
+                    .filter(|(entity, _)| *entity == hit.0)
+                    .map(|(_, first)| first)
+                else {
+                    return;
+                };
+                clipboard.box_corner = None;
+                let radius = brush.radius.ceil() as i32;
+                let (min, max) = (first.min(corner) - radius, first.max(corner) + radius);
+                let clip = VoxelClip::from_box(
+                    &e.lock().unwrap(),
+                    ICoords {
+                        x: min.x,
+                        y: min.y,
+                        z: min.z,
+                    },
+                    ICoords {
+                        x: max.x,
+                        y: max.y,
+                        z: max.z,
+                    },
+                );
+                clipboard.clip = Some(clip);
+                return;
+            }
+            SculptTool::Paste => {
+                let Some(clip) = clipboard.clip.as_ref() else {
+                    return;
+                };
+                let blend = clipboard.blend;
+                let rotation = |direction| clipboard.paste_rotation(clip, direction);
+                Box::new(move |entity| {
+                    entity.paste(clip, local_hit_point, direction, rotation, blend)
+                })
+            }
+        };
+
+        // voxels an edit at a position can change
+        let bounds = |position: Vec3, direction: Vec3| match (tool.0, clipboard.clip.as_ref()) {
+            (SculptTool::Paste, Some(clip)) => {
+                clip.bounds(position, clipboard.paste_rotation(clip, direction))
+            }
+            _ => ProceduralEntity::brush_bounds(position, &brush),
+        };
+
+        // edit the entity and regenerate its vertices, keeping the voxels it can change for the
+        // history (the edit is repeated at the symmetric positions of the entity)
+        let (before, edit) = {
             let mut entity = e.lock().unwrap();
-            if fill_mode.0 {
-                entity.fill(local_hit_point, 0.3, 2)
-            } else {
-                entity.carve(local_hit_point, 0.2, 2)
+            let images = entity.symmetry.images(local_hit_point, direction);
+            let before = EditSnapshot::new(
+                &entity,
+                images
+                    .iter()
+                    .map(|&(position, direction)| bounds(position, direction)),
+            );
+            let edit = apply(&mut entity);
+            (before, edit)
+        };
+        let mut new_entities = match edit {
+            Ok((new_entities, report)) => {
+                last_report.0 = Some(Ok(report));
+                new_entities
+            }
+            Err(err) => {
+                last_report.0 = Some(Err(format!("ignoring edit: {}", err)));
+                return;
             }
         };
 
+        // offsets of the fields of the new entities in the edited one
+        let mut offsets = Vec::new();
         for ent in new_entities.iter_mut() {
+            let mut offset = Coords::default();
             if ent.modification_count >= ent.modification_threshold {
-                ent.minimize_field_size();
+                offset = ent.minimize_field_size();
                 ent.modification_count = 0; // Reset modification count
             }
+            offsets.push(offset);
             ent.generate_vertices();
         }
 
         // despawn the entity and respawn it entirely
         commands.entity(hit.0).despawn_recursive();
 
-        for ent in new_entities.iter_mut() {
+        let mut new_ids = Vec::new();
+        for (ent, offset) in new_entities.iter_mut().zip(offsets.iter()) {
+            // cropped fields stay where they were
+            let offset = Vec3::new(offset.x as f32, offset.y as f32, offset.z as f32);
+            let mut transform = *t;
+            transform.translation += t.rotation * (offset * t.scale);
             let ac_mtx_entity = Arc::new(Mutex::new(ent.clone()));
             let new_en = EntityMeshComponent::respawn(
                 &mut commands,
                 &mut meshes,
                 &mut materials,
+                &registry,
                 Arc::clone(&ac_mtx_entity),
-                t.clone(),
+                transform,
                 lv.clone(),
                 av.clone(),
             );
@@ -68,7 +229,22 @@ pub fn entity_deform_system(
             // replace the entity in proc_entities
             // we insert first so that the reference count of ProceduralEntity remains positive
             proc_entities.0.insert(new_en, Arc::clone(&ac_mtx_entity));
+            new_ids.push(new_en);
         }
         proc_entities.0.remove(&hit.0);
+        if let Some((entities, _)) = last_hit.as_mut() {
+            *entities = new_ids.clone();
+        }
+
+        let fragments: Vec<_> = new_ids
+            .into_iter()
+            .zip(new_entities.iter())
+            .zip(offsets)
+            .map(|((id, ent), offset)| (id, ent, offset))
+            .collect();
+        history.record(hit.0, *t, &before, &e.lock().unwrap(), &fragments);
+    } else {
+        // the stroke ends when nothing gets hit
+        *last_hit = None;
     }
 }
@@ -0,0 +1,68 @@
+use bevy::prelude::*;
+
+use crate::common::voxel_material::MaterialRegistry;
+use crate::entity_mesh::{EntityMeshComponent, MeshLod};
+use crate::resources::{LodSettings, ProcEntities};
+
+/// Picks the level of detail of each mesh block of the entities from its distance to the camera,
+/// and rebuilds the rendered mesh when they change
+/// Colliders are left untouched, physics always uses the full resolution mesh
+pub fn entity_lod_system(
+    mut meshes: ResMut<Assets<Mesh>>,
+    proc_entities: Res<ProcEntities>,
+    lod_settings: Res<LodSettings>,
+    registry: Res<MaterialRegistry>,
+    camera_q: Query<&GlobalTransform, With<Camera3d>>,
+    mut entity_q: Query<
+        (Entity, &GlobalTransform, &Mesh3d, &mut MeshLod),
+        With<EntityMeshComponent>,
+    >,
+) {
+    let Ok(camera) = camera_q.get_single() else {
+        return;
+    };
+
+    for (id, transform, mesh, mut lod) in entity_q.iter_mut() {
+        let Some(entity) = proc_entities.0.get(&id) else {
+            continue;
+        };
+        let mut entity = entity.lock().unwrap();
+
+        let mut levels: Vec<usize> = entity
+            .block_centers()
+            .into_iter()
+            .map(|center| {
+                let distance = transform
+                    .transform_point(center)
+                    .distance(camera.translation());
+                lod_settings
+                    .distances
+                    .iter()
+                    .filter(|&&d| distance > d)
+                    .count()
+            })
+            .collect();
+        if levels.iter().all(|&level| level == 0) {
+            levels.clear();
+        }
+
+        if levels == lod.0 {
+            continue;
+        }
+
+        let normal_mode = entity.normal_mode;
+        let texture_scale = entity.texture_scale;
+        let (vertices, indices) = entity.render_mesh(&levels);
+        meshes.insert(
+            &mesh.0,
+            EntityMeshComponent::generate_mesh(
+                &vertices,
+                &indices,
+                normal_mode,
+                texture_scale,
+                &registry,
+            ),
+        );
+        lod.0 = levels;
+    }
+}
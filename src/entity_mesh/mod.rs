@@ -7,33 +7,91 @@ use bevy::render::mesh::{self, PrimitiveTopology};
 use bevy::utils::Instant;
 
 use crate::common::vertex::Vertex;
-use crate::common::voxel_material::VoxelMaterial;
-use crate::procedural_entity::ProceduralEntity;
+use crate::common::voxel_material::MaterialRegistry;
+use crate::mesher::MesherKind;
+use crate::procedural_entity::{NormalMode, ProceduralEntity};
+use crate::resources::ProcEntities;
 use crate::Cube;
 use std::sync::{Arc, Mutex};
 
 #[derive(Component, Clone, Copy)]
 pub struct EntityMeshComponent;
 
+/// Levels of detail of the mesh blocks of an entity currently rendered (see
+/// ProceduralEntity::lod_mesh), empty when they are all at full resolution
+#[derive(Component, Clone, Default)]
+pub struct MeshLod(pub Vec<usize>);
+
+/// Marks an entity whose mesh settings changed, its mesh and collider get rebuilt by
+/// refresh_mesh_system
+#[derive(Component)]
+pub struct StaleMesh;
+
+/// Remeshes the entities marked with StaleMesh, rebuilding their rendered mesh (at their current
+/// levels of detail) and their collider
+pub fn refresh_mesh_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    proc_entities: Res<ProcEntities>,
+    registry: Res<MaterialRegistry>,
+    stale_q: Query<(Entity, &Mesh3d, &MeshLod), With<StaleMesh>>,
+) {
+    for (id, mesh, lod) in stale_q.iter() {
+        commands.entity(id).remove::<StaleMesh>();
+        let Some(entity) = proc_entities.0.get(&id) else {
+            continue;
+        };
+        let mut entity = entity.lock().unwrap();
+        entity.generate_vertices();
+
+        let (vertices, indices) = entity.render_mesh(&lod.0);
+        meshes.insert(
+            &mesh.0,
+            EntityMeshComponent::generate_mesh(
+                &vertices,
+                &indices,
+                entity.normal_mode,
+                entity.texture_scale,
+                &registry,
+            ),
+        );
+        commands
+            .entity(id)
+            .try_insert(EntityMeshComponent::entity_collider(&mut entity));
+    }
+}
+
 impl EntityMeshComponent {
     pub fn respawn(
         commands: &mut Commands,
         meshes: &mut Assets<Mesh>,
         materials: &mut Assets<StandardMaterial>,
+        registry: &MaterialRegistry,
         entity: Arc<Mutex<ProceduralEntity>>,
         transform: Transform,
         lv: LinearVelocity,
         av: AngularVelocity,
     ) -> Entity {
-        // we cannot call entity.lock() multiple times during the same function call
-        let vertices = entity.lock().unwrap().vertices.clone();
-
-        let mesh = Self::generate_mesh(vertices);
+        let (mesh, collider) = {
+            // we cannot call entity.lock() multiple times during the same function call
+            let mut entity = entity.lock().unwrap();
+            let (vertices, indices) = entity.render_mesh(&[]);
+            (
+                Self::generate_mesh(
+                    &vertices,
+                    &indices,
+                    entity.normal_mode,
+                    entity.texture_scale,
+                    registry,
+                ),
+                Self::entity_collider(&mut entity),
+            )
+        };
 
         let id = commands
             .spawn((
                 RigidBody::Dynamic,
-                Collider::trimesh_from_mesh(&mesh).unwrap(),
+                collider,
                 Mesh3d(meshes.add(mesh)),
                 MeshMaterial3d(materials.add(StandardMaterial {
                     base_color: Color::srgb_u8(124, 144, 255),
@@ -44,6 +102,7 @@ impl EntityMeshComponent {
                 av,
             ))
             .insert(EntityMeshComponent)
+            .insert(MeshLod::default())
             .insert(Cube)
             .observe(crate::observers::on_drag_manipulate)
             .id();
@@ -55,17 +114,29 @@ impl EntityMeshComponent {
         commands: &mut Commands,
         meshes: &mut Assets<Mesh>,
         materials: &mut Assets<StandardMaterial>,
+        registry: &MaterialRegistry,
         entity: Arc<Mutex<ProceduralEntity>>,
     ) -> Entity {
-        // we cannot call entity.lock() multiple times during the same function call
-        let vertices = entity.lock().unwrap().vertices.clone();
-
-        let mesh = Self::generate_mesh(vertices);
+        let (mesh, collider) = {
+            // we cannot call entity.lock() multiple times during the same function call
+            let mut entity = entity.lock().unwrap();
+            let (vertices, indices) = entity.render_mesh(&[]);
+            (
+                Self::generate_mesh(
+                    &vertices,
+                    &indices,
+                    entity.normal_mode,
+                    entity.texture_scale,
+                    registry,
+                ),
+                Self::entity_collider(&mut entity),
+            )
+        };
 
         let id = commands
             .spawn((
                 RigidBody::Dynamic,
-                Collider::trimesh_from_mesh(&mesh).unwrap(),
+                collider,
                 Mesh3d(meshes.add(mesh)),
                 MeshMaterial3d(materials.add(StandardMaterial {
                     base_color: Color::srgb_u8(124, 144, 255),
@@ -76,6 +147,7 @@ impl EntityMeshComponent {
                     .with_scale(Vec3::new(0.2, 0.2, 0.2)),
             ))
             .insert(EntityMeshComponent)
+            .insert(MeshLod::default())
             .insert(Cube)
             .observe(crate::observers::on_drag_manipulate)
             .id();
@@ -83,27 +155,132 @@ impl EntityMeshComponent {
         return id;
     }
 
-    pub fn generate_mesh(vertices: Vec<Vertex>) -> Mesh {
-        let mut indices_vec = Vec::new();
+    /// Blocky entities get a compound of boxes matching their voxels, others a trimesh of their
+    /// surface
+    pub fn entity_collider(entity: &mut ProceduralEntity) -> Collider {
+        if entity.mesher == MesherKind::Blocky {
+            return Self::generate_box_collider(&entity.collider_boxes());
+        }
+        let (vertices, indices) = entity.collider_mesh();
+        Self::generate_collider(&vertices, &indices)
+    }
+
+    /// Builds a compound collider out of boxes given as their min and max corners
+    pub fn generate_box_collider(boxes: &Vec<(Vec3, Vec3)>) -> Collider {
+        Collider::compound(
+            boxes
+                .iter()
+                .map(|&(min, max)| {
+                    let size = max - min;
+                    (
+                        (min + max) / 2.,
+                        Quat::IDENTITY,
+                        Collider::cuboid(size.x, size.y, size.z),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Builds the trimesh collider of an entity, always using the shared vertices (whatever the
+    /// normal mode of the rendered mesh is)
+    pub fn generate_collider(vertices: &Vec<Vertex>, indices: &Vec<u32>) -> Collider {
+        let positions = vertices
+            .iter()
+            .map(|v| Vec3::from(v.pos.into_inners_arr()))
+            .collect();
+        let triangles = indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect();
+        Collider::trimesh(positions, triangles)
+    }
 
+    /// Builds a bevy mesh out of the indexed vertices produced by marching cubes
+    /// With flat normals, vertices are duplicated for each triangle so that they can hold the
+    /// normal of their face
+    /// Each vertex gets the colour of its material, darkened by its ambient occlusion, so
+    /// triangles lying between two materials blend their colours
+    /// UVs are projected from the position of the vertices along the dominant axis of the normal
+    /// of each triangle (triplanar mapping), texture_scale being the number of texture repeats
+    /// per voxel. Since they only depend on positions, textures do not slide when the shape
+    /// changes. Vertices shared by triangles with different projections are duplicated.
+    pub fn generate_mesh(
+        vertices: &Vec<Vertex>,
+        indices: &Vec<u32>,
+        normal_mode: NormalMode,
+        texture_scale: f32,
+        registry: &MaterialRegistry,
+    ) -> Mesh {
         let mut positions: Vec<[f32; 3]> = Vec::new();
         let mut normals: Vec<[f32; 3]> = Vec::new();
         let mut colors: Vec<[f32; 4]> = Vec::new();
         let mut uvs: Vec<[f32; 2]> = Vec::new();
-        for vertex in vertices.iter() {
-            indices_vec.push(positions.len() as u32);
-            positions.push(vertex.pos.into_inners_arr());
-            normals.push(vertex.normal.into());
-            let c = if vertex.voxel_material == VoxelMaterial::STONE {
-                [0.4, 0.4, 0.4, 1.0]
+        let mut tangents: Vec<[f32; 4]> = Vec::new();
+        let mut push_vertex = |vertex: &Vertex, normal: Vec3, projection: usize| -> u32 {
+            let pos = Vec3::from(vertex.pos.into_inners_arr());
+            let (u_axis, v_axis) = Self::projection_axes(projection);
+            // tangent along the u axis of the texture, in the plane of the surface
+            let tangent = (u_axis - normal * normal.dot(u_axis)).normalize_or(u_axis);
+            let handedness = if normal.cross(tangent).dot(v_axis) >= 0. {
+                1.
             } else {
-                [0.6, 0.5, 0.2, 1.0]
+                -1.
             };
-            colors.push(c);
-            uvs.push([1., 1.]);
-        }
 
-        let indices = mesh::Indices::U32(indices_vec);
+            positions.push(pos.into());
+            normals.push(normal.into());
+            let color = LinearRgba::from(registry.color(vertex.voxel_material));
+            colors.push(
+                (color * vertex.occlusion)
+                    .with_alpha(color.alpha)
+                    .to_f32_array(),
+            );
+            uvs.push([
+                pos.dot(u_axis) * texture_scale,
+                pos.dot(v_axis) * texture_scale,
+            ]);
+            tangents.push(tangent.extend(handedness).into());
+            positions.len() as u32 - 1
+        };
+
+        let indices = match normal_mode {
+            NormalMode::Smooth => {
+                // one output vertex per vertex and projection used by its triangles
+                let mut projected = vec![[u32::MAX; 6]; vertices.len()];
+                let mut mesh_indices = Vec::with_capacity(indices.len());
+                for t in indices.chunks_exact(3) {
+                    let normal = t.iter().map(|&i| vertices[i as usize].normal).sum::<Vec3>();
+                    let projection = Self::projection(normal);
+                    for &i in t {
+                        let vertex = &vertices[i as usize];
+                        let index = &mut projected[i as usize][projection];
+                        if *index == u32::MAX {
+                            *index = push_vertex(vertex, vertex.normal, projection);
+                        }
+                        mesh_indices.push(*index);
+                    }
+                }
+                mesh_indices
+            }
+            NormalMode::Flat => {
+                for t in indices.chunks_exact(3) {
+                    let [a, b, c] = [t[0], t[1], t[2]].map(|i| &vertices[i as usize]);
+                    let [a_pos, b_pos, c_pos] =
+                        [a, b, c].map(|v| Vec3::from(v.pos.into_inners_arr()));
+                    // degenerate triangles have no face normal, keep the smooth ones instead
+                    let normal = (b_pos - a_pos).cross(c_pos - a_pos).try_normalize();
+                    let projection =
+                        Self::projection(normal.unwrap_or(a.normal + b.normal + c.normal));
+                    for v in [a, b, c] {
+                        push_vertex(v, normal.unwrap_or(v.normal), projection);
+                    }
+                }
+                (0..indices.len() as u32).collect()
+            }
+        };
+
+        let indices = mesh::Indices::U32(indices);
 
         // TODO: Maybe use a triangle strip instead ?
         let mut mesh = Mesh::new(
@@ -115,7 +292,37 @@ impl EntityMeshComponent {
         mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
         mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
         mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, tangents);
 
         mesh
     }
+
+    /// Triplanar projection of a surface, the dominant axis of its normal (0, 1, 2 for x, y, z)
+    /// plus 3 if the normal points towards negative values
+    fn projection(normal: Vec3) -> usize {
+        let abs = normal.abs();
+        let axis = if abs.x >= abs.y && abs.x >= abs.z {
+            0
+        } else if abs.y >= abs.z {
+            1
+        } else {
+            2
+        };
+        if normal[axis] < 0. {
+            axis + 3
+        } else {
+            axis
+        }
+    }
+
+    /// Directions of the u and v texture coordinates for a projection, oriented so that textures
+    /// are not mirrored when looking at the surface from outside
+    fn projection_axes(projection: usize) -> (Vec3, Vec3) {
+        let sign = if projection >= 3 { -1. } else { 1. };
+        match projection % 3 {
+            0 => (Vec3::new(0., 0., -sign), Vec3::NEG_Y),
+            1 => (Vec3::X, Vec3::new(0., 0., sign)),
+            _ => (Vec3::new(sign, 0., 0.), Vec3::NEG_Y),
+        }
+    }
 }
@@ -0,0 +1,161 @@
+use bevy::math::Vec3;
+
+use crate::common::field_extent::FieldExtent;
+use crate::noise::{Noise, SeededRng};
+use crate::procedural_entity::{MaterialLayering, ProceduralEntity};
+use crate::sdf::SdfNode;
+
+/// Family of shapes built by a ShapeGenerator
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GeneratorKind {
+    /// Elongated and angular, with ridges
+    Rock,
+    /// Round and lumpy, covered with craters
+    Asteroid,
+    /// Squat and smooth
+    Boulder,
+}
+
+impl GeneratorKind {
+    pub const ALL: [GeneratorKind; 3] = [
+        GeneratorKind::Rock,
+        GeneratorKind::Asteroid,
+        GeneratorKind::Boulder,
+    ];
+}
+
+/// Builds the voxels of an entity by perturbing a base shape with fractal noise
+/// The same settings (and seed) always give the exact same voxel field
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ShapeGenerator {
+    pub kind: GeneratorKind,
+    pub seed: u64,
+    // amount of noise displacing the surface, 0 keeps the base shape, 1 is very rough
+    pub roughness: f32,
+    pub crater_count: usize,
+    pub layering: MaterialLayering,
+}
+
+impl ShapeGenerator {
+    pub fn new(kind: GeneratorKind, seed: u64) -> Self {
+        let (roughness, crater_count) = match kind {
+            GeneratorKind::Rock => (0.6, 0),
+            GeneratorKind::Asteroid => (0.4, 6),
+            GeneratorKind::Boulder => (0.25, 0),
+        };
+        Self {
+            kind,
+            seed,
+            roughness,
+            crater_count,
+            layering: MaterialLayering::default(),
+        }
+    }
+
+    /// Generator of any kind, picked from the seed
+    pub fn from_seed(seed: u64) -> Self {
+        Self::new(
+            GeneratorKind::ALL[(seed % GeneratorKind::ALL.len() as u64) as usize],
+            seed,
+        )
+    }
+
+    /// Entity filled with a new shape, centred in its field
+    pub fn generate(&self, extent: FieldExtent) -> ProceduralEntity {
+        let mut entity = ProceduralEntity::new(extent);
+        self.fill(&mut entity);
+        entity
+    }
+
+    /// Replaces the voxels of an entity by a new shape, centred in its field
+    pub fn fill(&self, entity: &mut ProceduralEntity) {
+        let extent = entity.extent;
+        let center = Vec3::new(extent.x as f32, extent.y as f32, extent.z as f32) / 2.;
+        // leaves room for the noise and a layer of air around the shape
+        let radius = extent.x.min(extent.y).min(extent.z) as f32 * 0.3;
+
+        let mut rng = SeededRng::new(self.seed);
+        let base = self.base_shape(&mut rng, center, radius);
+        let noise = Noise::new((self.seed ^ (self.seed >> 32)) as u32);
+        let (kind, roughness) = (self.kind, self.roughness);
+        let distance = |pos: Vec3| {
+            // noise coordinates scaled to the size of the shape, so a seed gives the same shape
+            // in any field
+            let p = (pos - center) / radius;
+            let (warp, displacement) = match kind {
+                GeneratorKind::Rock => (
+                    0.3,
+                    0.6 * noise.fbm(p * 2., 4) - 0.5 * noise.ridged(p * 1.5, 3),
+                ),
+                GeneratorKind::Asteroid => (0.5, noise.fbm(p * 2.5, 5)),
+                GeneratorKind::Boulder => (0.15, noise.fbm(p * 1.5, 3)),
+            };
+            let warped = center + noise.warp(p, warp * roughness, 3) * radius;
+            base.distance(warped) - displacement * roughness * radius * 0.25
+        };
+        entity.fill_from_distance(distance, self.layering);
+    }
+
+    fn base_shape(&self, rng: &mut SeededRng, center: Vec3, radius: f32) -> SdfNode {
+        let orientation = rng.rotation();
+        let shape = match self.kind {
+            GeneratorKind::Rock => {
+                SdfNode::rounded_box(Vec3::ZERO, Vec3::new(1., 0.6, 0.75) * radius, 0.3 * radius)
+                    .transformed(orientation, center)
+            }
+            GeneratorKind::Asteroid => SdfNode::sphere(center, radius),
+            GeneratorKind::Boulder => {
+                SdfNode::rounded_box(center, Vec3::new(1., 0.7, 0.9) * radius, 0.6 * radius)
+            }
+        };
+
+        // bowls carved around the surface of the base shape
+        (0..self.crater_count).fold(shape, |shape, _| {
+            let crater_radius = rng.range(0.15, 0.35) * radius;
+            let crater_center = center + rng.direction() * (radius + 0.5 * crater_radius);
+            shape.smooth_subtraction(
+                SdfNode::sphere(crater_center, crater_radius),
+                0.3 * crater_radius,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bits of the values and materials of the voxels, for exact comparisons
+    fn field_bits(generator: ShapeGenerator) -> Vec<(u32, u16)> {
+        generator
+            .generate(FieldExtent::new(24, 20, 28))
+            .voxel_field
+            .iter()
+            .map(|voxel| (voxel.value.to_bits(), voxel.material.0))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_field() {
+        for kind in GeneratorKind::ALL {
+            let generator = ShapeGenerator::new(kind, 42);
+            assert!(
+                field_bits(generator) == field_bits(generator),
+                "{:?} generated different fields from the same seed",
+                kind
+            );
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_fields() {
+        for kind in GeneratorKind::ALL {
+            assert!(
+                field_bits(ShapeGenerator::new(kind, 1))
+                    != field_bits(ShapeGenerator::new(kind, 2)),
+                "{:?} generated the same field from different seeds",
+                kind
+            );
+        }
+    }
+}
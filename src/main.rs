@@ -1,19 +1,37 @@
 // #![feature(portable_simd)]
 #![feature(stdarch_x86_avx512)]
 
+mod brush;
 mod camera;
+mod clipboard;
 use crate::resources::FillMode;
 mod common;
+mod edit_history;
 mod entity_deform;
+mod entity_lod;
 mod entity_mesh;
+mod generators;
 mod marching_cubes;
+mod mesh_quality;
+mod mesh_simplify;
+mod mesher;
+mod noise;
 mod observers;
 mod procedural_entity;
 mod resources;
+mod sdf;
+mod symmetry;
 mod ui;
-use crate::entity_mesh::EntityMeshComponent;
+use crate::common::field_extent::FieldExtent;
+use crate::common::voxel_material::{MaterialRegistry, VoxelMaterial};
+use crate::entity_mesh::{EntityMeshComponent, StaleMesh};
+use crate::generators::ShapeGenerator;
+use crate::marching_cubes::MarchingCubesVariant;
+use crate::mesher::MesherKind;
+use crate::symmetry::{Symmetry, SymmetryKind};
 use avian3d::prelude::*;
 use bevy::prelude::*;
+use bevy_egui::EguiPlugin;
 use camera::*;
 use entity_deform::*;
 use observers::*;
@@ -31,10 +49,18 @@ fn main() {
             // enable for physics debug rendering
             // PhysicsDebugPlugin::default(),
         ))
-        // .add_plugins(EguiPlugin)
+        .add_plugins(EguiPlugin)
         .insert_resource(ProcEntities::default())
         .insert_resource(resources::RayMeshHits::default())
         .insert_resource(FillMode::default())
+        .insert_resource(ActiveTool::default())
+        .insert_resource(PaintMaterial::default())
+        .insert_resource(LastEditReport::default())
+        .insert_resource(MaterialRegistry::default())
+        .insert_resource(LodSettings::default())
+        .insert_resource(brush::ActiveBrush::default())
+        .insert_resource(edit_history::EditHistory::default())
+        .insert_resource(clipboard::VoxelClipboard::default())
         .add_systems(Startup, setup) // Add a basic 3D scene setup
         .add_systems(Startup, spawn_camera)
         .add_systems(
@@ -42,13 +68,29 @@ fn main() {
             (
                 handle_camera.run_if(any_with_component::<FirstPersonState>),
                 entity_deform_system,
+                entity_lod::entity_lod_system,
             )
                 .chain(),
         )
         .add_systems(Update, grab_mouse)
         .add_systems(Update, toggle_fill_mode)
+        .add_systems(Update, cycle_sculpt_tool)
+        .add_systems(Update, cycle_paint_material)
+        .add_systems(Update, cycle_symmetry)
+        .add_systems(Update, toggle_normal_mode)
+        .add_systems(Update, cycle_mesher)
+        .add_systems(Update, entity_mesh::refresh_mesh_system)
+        .add_systems(Update, brush::brush_input_system)
+        .add_systems(Update, clipboard::clipboard_input_system)
+        .add_systems(
+            Update,
+            edit_history::edit_history_system.before(entity_deform_system),
+        )
+        .add_systems(Update, validate_meshes)
         // .add_systems(Update, cursor_recenter)
         // .add_systems(Update, ui_main_system)
+        .add_systems(Update, ui::brush_ui_system)
+        .add_systems(Update, ui::edit_report_ui_system)
         .run();
 }
 
@@ -62,11 +104,180 @@ pub fn toggle_fill_mode(
     }
 }
 
+pub fn cycle_sculpt_tool(
+    mut tool: ResMut<ActiveTool>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyT) {
+        tool.0 = match tool.0 {
+            SculptTool::Deform => SculptTool::Smooth,
+            SculptTool::Smooth => SculptTool::Flatten,
+            SculptTool::Flatten => SculptTool::Smear,
+            SculptTool::Smear => SculptTool::Paint,
+            SculptTool::Paint => SculptTool::Copy,
+            SculptTool::Copy => SculptTool::CopyBox,
+            SculptTool::CopyBox => SculptTool::Paste,
+            SculptTool::Paste => SculptTool::Deform,
+        };
+        println!("Sculpt tool: {:?}", tool.0);
+    }
+}
+
+/// Cycles through the registered materials painted by the paint tool (all but air)
+pub fn cycle_paint_material(
+    mut paint_material: ResMut<PaintMaterial>,
+    registry: Res<MaterialRegistry>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyM) {
+        let materials: Vec<_> = registry
+            .materials()
+            .filter(|(material, _)| *material != VoxelMaterial::AIR)
+            .collect();
+        if materials.is_empty() {
+            return;
+        }
+        let next = materials
+            .iter()
+            .position(|(material, _)| *material == paint_material.0)
+            .map_or(0, |i| (i + 1) % materials.len());
+        let (material, definition) = materials[next];
+        paint_material.0 = material;
+        println!("Paint material: {}", definition.name);
+    }
+}
+
+/// Procedural entity the camera looks at
+fn targeted_entity(
+    camera: &Transform,
+    raycast: &mut MeshRayCast,
+    proc_entities: &ProcEntities,
+) -> Option<Entity> {
+    let ray = Ray3d::new(camera.translation, camera.forward());
+    raycast
+        .cast_ray(ray, &RayCastSettings::default())
+        .first()
+        .map(|&(id, _)| id)
+        .filter(|id| proc_entities.0.contains_key(id))
+}
+
+/// Cycles the symmetry of the edits of the procedural entity the camera looks at, centred on
+/// its field: none, mirror across x, mirror across x and z, radial with 6 copies around y
+pub fn cycle_symmetry(
+    proc_entities: Res<ProcEntities>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    camera_q: Query<&Transform, With<FirstPersonState>>,
+    mut raycast: MeshRayCast,
+) {
+    if !keyboard_input.just_pressed(KeyCode::KeyX) {
+        return;
+    }
+    let Ok(camera) = camera_q.get_single() else {
+        return;
+    };
+    let Some(id) = targeted_entity(camera, &mut raycast, &proc_entities) else {
+        return;
+    };
+    let mut entity = proc_entities.0[&id].lock().unwrap();
+    let extent = entity.extent;
+    let center = Vec3::new(
+        extent.x as f32 - 1.,
+        extent.y as f32 - 1.,
+        extent.z as f32 - 1.,
+    ) / 2.;
+    entity.symmetry = match entity.symmetry.kind {
+        SymmetryKind::None => Symmetry::mirror(center, true, false, false),
+        SymmetryKind::Mirror { z: false, .. } => Symmetry::mirror(center, true, false, true),
+        SymmetryKind::Mirror { .. } => Symmetry::radial(center, Vec3::Y, 6),
+        SymmetryKind::Radial { .. } => Symmetry::default(),
+    };
+    println!("Symmetry: {:?}", entity.symmetry.kind);
+}
+
+/// Switches the procedural entity the camera looks at between smooth and flat normals
+pub fn toggle_normal_mode(
+    mut commands: Commands,
+    proc_entities: Res<ProcEntities>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    camera_q: Query<&Transform, With<FirstPersonState>>,
+    mut raycast: MeshRayCast,
+) {
+    if !keyboard_input.just_pressed(KeyCode::KeyH) {
+        return;
+    }
+    let Ok(camera) = camera_q.get_single() else {
+        return;
+    };
+    let Some(id) = targeted_entity(camera, &mut raycast, &proc_entities) else {
+        return;
+    };
+    let mut entity = proc_entities.0[&id].lock().unwrap();
+    entity.normal_mode = match entity.normal_mode {
+        NormalMode::Smooth => NormalMode::Flat,
+        NormalMode::Flat => NormalMode::Smooth,
+    };
+    commands.entity(id).try_insert(StaleMesh);
+    println!("Normals: {:?}", entity.normal_mode);
+}
+
+/// Cycles the mesher of the procedural entity the camera looks at: marching cubes (with the
+/// asymptotic decider, then the classic table), surface nets, dual contouring, blocky
+pub fn cycle_mesher(
+    mut commands: Commands,
+    proc_entities: Res<ProcEntities>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    camera_q: Query<&Transform, With<FirstPersonState>>,
+    mut raycast: MeshRayCast,
+) {
+    if !keyboard_input.just_pressed(KeyCode::KeyG) {
+        return;
+    }
+    let Ok(camera) = camera_q.get_single() else {
+        return;
+    };
+    let Some(id) = targeted_entity(camera, &mut raycast, &proc_entities) else {
+        return;
+    };
+    let mut entity = proc_entities.0[&id].lock().unwrap();
+    entity.mesher = match entity.mesher {
+        MesherKind::MarchingCubes(MarchingCubesVariant::AsymptoticDecider) => {
+            MesherKind::MarchingCubes(MarchingCubesVariant::Classic)
+        }
+        MesherKind::MarchingCubes(MarchingCubesVariant::Classic) => MesherKind::SurfaceNets,
+        MesherKind::SurfaceNets => MesherKind::DualContouring,
+        MesherKind::DualContouring => MesherKind::Blocky,
+        MesherKind::Blocky => MesherKind::default(),
+    };
+    commands.entity(id).try_insert(StaleMesh);
+    println!("Mesher: {:?}", entity.mesher);
+}
+
+/// Prints the mesh validation report and the mesh stats of every procedural entity
+pub fn validate_meshes(
+    proc_entities: Res<ProcEntities>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyV) {
+        for (id, entity) in proc_entities.0.iter() {
+            let entity = entity.lock().unwrap();
+            let report = entity.validate_mesh();
+            let closed = if report.is_closed_manifold() {
+                "closed"
+            } else {
+                "NOT closed"
+            };
+            println!("mesh of {:?} is {}: {}", id, closed, report);
+            println!("{}", entity.stats);
+        }
+    }
+}
+
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut proc_entities: ResMut<ProcEntities>,
+    registry: Res<MaterialRegistry>,
 ) {
     commands
         .spawn((
@@ -127,7 +338,7 @@ fn setup(
         Transform::from_xyz(30.0, 8.0, 30.0),
     ));
 
-    let entity = Arc::new(Mutex::new(ProceduralEntity::new(40)));
+    let entity = Arc::new(Mutex::new(ProceduralEntity::new(FieldExtent::cube(40))));
     {
         let mut entity_guard = entity.lock().unwrap();
         entity_guard.generate_voxels();
@@ -138,6 +349,7 @@ fn setup(
         &mut commands,
         &mut meshes,
         &mut materials,
+        &registry,
         Arc::clone(&entity),
     );
     proc_entities.0.insert(id, entity);
@@ -155,6 +367,7 @@ fn on_click_spawn_cube(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut proc_entities: ResMut<ProcEntities>,
+    registry: Res<MaterialRegistry>,
     mut num: Local<CubeCount>,
 ) {
     // spawn cube
@@ -171,13 +384,15 @@ fn on_click_spawn_cube(
         .observe(on_drag_end);
     (*num).0 += 1;
 
-    let entity = Arc::new(Mutex::new(ProceduralEntity::new(40)));
-    entity.lock().unwrap().generate_voxels();
+    // a different shape for each spawned entity
+    let generator = ShapeGenerator::from_seed((*num).0 as u64);
+    let entity = Arc::new(Mutex::new(generator.generate(FieldExtent::cube(40))));
     entity.lock().unwrap().generate_vertices();
     let id = EntityMeshComponent::spawn(
         &mut commands,
         &mut meshes,
         &mut materials,
+        &registry,
         Arc::clone(&entity),
     );
     proc_entities.0.insert(id, entity);
@@ -0,0 +1,144 @@
+use super::{
+    get_base_nodes, get_or_insert_vertex, MeshContext, Nodes, VoxelsBlock, NODES_POS_COUNT,
+};
+use crate::common::coords::*;
+use crate::common::vertex::Vertex;
+use crate::mesher::UNSHARED_VERTEX;
+use bevy::math::Vec3;
+
+/// Corners of each face of a cube, counterclockwise when looking at the face from outside of the
+/// cube, along with the node lying on the edge going from each corner to the next one
+const FACES: [([(usize, usize, usize); 4], [usize; 4]); 6] = [
+    // -x face
+    ([(0, 0, 0), (0, 0, 1), (0, 1, 1), (0, 1, 0)], [3, 8, 7, 11]),
+    // +x face
+    ([(1, 1, 0), (1, 1, 1), (1, 0, 1), (1, 0, 0)], [5, 9, 1, 10]),
+    // -y face
+    ([(1, 0, 0), (1, 0, 1), (0, 0, 1), (0, 0, 0)], [1, 0, 3, 2]),
+    // +y face
+    ([(0, 1, 0), (0, 1, 1), (1, 1, 1), (1, 1, 0)], [7, 4, 5, 6]),
+    // -z face
+    ([(0, 0, 0), (0, 1, 0), (1, 1, 0), (1, 0, 0)], [11, 6, 10, 2]),
+    // +z face
+    ([(1, 0, 1), (1, 1, 1), (0, 1, 1), (0, 0, 1)], [9, 4, 8, 0]),
+];
+
+fn shares_face(a: usize, b: usize) -> bool {
+    FACES
+        .iter()
+        .any(|(_, edges)| edges.contains(&a) && edges.contains(&b))
+}
+
+/// Generates the polygons of the surface crossing a cube by walking along its faces
+/// Each face links the nodes lying on its edges two by two. Ambiguous faces (with two opposite
+/// solid corners) are resolved with the asymptotic decider, which only depends on the four
+/// values of the face: both cubes sharing a face always agree, so the surface has no holes.
+pub(super) fn append_cube_polygons(
+    ctx: &mut MeshContext,
+    pos: LocalVoxelCoords,
+    voxels: VoxelsBlock,
+    nodes: Nodes,
+) {
+    // next[n] is the node following node n on the polygon it belongs to
+    let mut next = [usize::MAX; NODES_POS_COUNT];
+    for (corners, edges) in FACES.iter() {
+        let values = corners.map(|(x, y, z)| voxels[x][y][z].value);
+        let solid = values.map(|v| v >= 0.);
+
+        // walking around the face, an edge is entered when going from air to solid
+        let entries: Vec<usize> = (0..4)
+            .filter(|&i| !solid[i] && solid[(i + 1) % 4])
+            .collect();
+        let exits: Vec<usize> = (0..4)
+            .filter(|&i| solid[i] && !solid[(i + 1) % 4])
+            .collect();
+
+        match entries.len() {
+            1 => next[edges[entries[0]]] = edges[exits[0]],
+            2 => {
+                // value of the bilinear interpolation of the face at its saddle point,
+                // a solid saddle means both solid corners are connected through the face
+                let saddle = (values[0] * values[2] - values[1] * values[3])
+                    / (values[0] + values[2] - values[1] - values[3]);
+                for &entry in entries.iter() {
+                    let exit = if saddle >= 0. {
+                        (entry + 3) % 4
+                    } else {
+                        (entry + 1) % 4
+                    };
+                    next[edges[entry]] = edges[exit];
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let base_nodes = get_base_nodes();
+    let mut visited = [false; NODES_POS_COUNT];
+    for start in 0..NODES_POS_COUNT {
+        if next[start] == usize::MAX || visited[start] {
+            continue;
+        }
+
+        let mut polygon_nodes = Vec::with_capacity(NODES_POS_COUNT);
+        let mut n = start;
+        while n != usize::MAX && !visited[n] {
+            visited[n] = true;
+            polygon_nodes.push(n);
+            n = next[n];
+        }
+        if n != start {
+            // every node has exactly one predecessor and one successor, this should not happen
+            continue;
+        }
+
+        let polygon: Vec<u32> = polygon_nodes
+            .iter()
+            .map(|&n| get_or_insert_vertex(ctx, pos, base_nodes[n], nodes[n]))
+            .collect();
+        triangulate_polygon(ctx, &polygon_nodes, &polygon);
+    }
+}
+
+/// Triangulates a polygon as a fan, unless every fan would create a diagonal between two nodes of
+/// the same face: the neighbouring cube could create that same diagonal, so the polygon is
+/// triangulated around its center instead
+fn triangulate_polygon(ctx: &mut MeshContext, polygon_nodes: &[usize], polygon: &[u32]) {
+    let n = polygon.len();
+    if n < 3 {
+        return;
+    }
+
+    let apex = (0..n)
+        .find(|&a| (2..n - 1).all(|k| !shares_face(polygon_nodes[a], polygon_nodes[(a + k) % n])));
+
+    if let Some(a) = apex {
+        for k in 1..n - 1 {
+            ctx.block
+                .push_triangle(polygon[a], polygon[(a + k) % n], polygon[(a + k + 1) % n]);
+        }
+        return;
+    }
+
+    let first = ctx.block.vertices[polygon[0] as usize].clone();
+    let mut pos = WorldCoords::new(0., 0., 0.);
+    let mut normal = Vec3::ZERO;
+    for &i in polygon.iter() {
+        pos = pos + ctx.block.vertices[i as usize].pos;
+        normal += ctx.block.vertices[i as usize].normal;
+    }
+
+    let center = ctx.block.vertices.len() as u32;
+    ctx.block.vertices.push(Vertex {
+        pos: pos * (1.0 / n as f32),
+        normal: normal.normalize_or_zero(),
+        ..first
+    });
+    // the center of the polygon only belongs to this cube
+    ctx.block.keys.push(UNSHARED_VERTEX);
+
+    for k in 0..n {
+        ctx.block
+            .push_triangle(center, polygon[k], polygon[(k + 1) % n]);
+    }
+}
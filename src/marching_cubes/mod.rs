@@ -1,57 +1,94 @@
 use crate::common::voxel_material::VoxelMaterial;
 use crate::common::{vertex::Vertex, voxels::Voxel};
 
-use bevy::{math::Vec3, prelude::Color};
+use bevy::prelude::Color;
 
 use crate::common::coords::*;
+use crate::common::field_extent::FieldExtent;
+use crate::common::region::Region;
+use crate::mesher::{density_gradient, get_voxel, MeshBlock, Mesher, MIN_EDGE_OFFSET};
 
+mod asymptotic_decider;
 mod table;
 
-// TODO: indexing, and spatial hierarchy for marching cubes
+// TODO: spatial hierarchy for marching cubes
 //
-// some ideas :
-// Reusing the vertices can save you memory and save time spent sending data to the GPU.
-// It's really only worth the trouble if you have a large number of voxels.
-// I've done this with marching cubes, but I'm not sure the same approach would work for tetrahedra.
-//
-// For marching cubes, the vertices lie along the edges. Each edge is shared by up to 4 cubes.
-// The total number of unique edges in each dim is equal to size+1 because the two end
-// voxels don't share their edges. So if you had nx*ny*nz voxels, you would have (nx+1)*(ny+1)*(nz+1) edges.
-// This can be pre-allocated as an array. Each edge can potentially have a vertex.
-// I used an integer index for tracking this, initialized to -1 to indicate empty/not yet calculated.
-// Then iterate over each voxel and generate the edge points. Look up the edge for each vertex.
-// If it's -1, add the vertex to the unique vertex array, assign the edge index to the current
-// vertex array position, and add the index to the index list. If the edge is already assigned a value >= 0,
-// then add that existing index to the index list.
+// Marching cubes can be applied on a region of the field only, producing a MeshBlock.
+// Vertices lie along the edges of the grid, and each edge is shared by up to 4 cubes.
+// Every grid point owns 3 edges (one along each axis, going towards the positive direction),
+// so an edge can be identified by the index of its base voxel and its axis.
+// The edge cache maps each edge to the index of the vertex generated on it (u32::MAX when no
+// vertex has been generated yet), so that neighbouring cubes reuse the same vertex.
+
+/// How the surface crossing each cube is triangulated
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum MarchingCubesVariant {
+    /// Classic 256 cases table, ambiguous faces are always resolved the same way whatever the
+    /// values of their corners, which can join or split parts of the surface incorrectly
+    Classic,
+    /// Ambiguous faces are resolved with the asymptotic decider, giving a closed surface
+    #[default]
+    AsymptoticDecider,
+}
+
+/// Marching cubes mesher, vertices lie on the edges of the grid and are keyed by the index of
+/// their edge
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct MarchingCubes {
+    pub variant: MarchingCubesVariant,
+}
+
+impl Mesher for MarchingCubes {
+    fn mesh_region(
+        &self,
+        block: &mut MeshBlock,
+        field: &Vec<Voxel>,
+        extent: FieldExtent,
+        region: Region,
+    ) {
+        find_triangles(block, field, extent, region, self.variant);
+    }
+}
 
 #[derive(Clone, Copy)]
 struct VertexNode {
     index: usize,
     pos: WorldCoords,
+    // base voxel of the edge the node lies on (relative to the cube) and axis of that edge
+    corner: LocalVoxelCoords,
+    axis: usize,
 }
 
 fn get_node_dn() -> VertexNode {
     VertexNode {
         index: 0,
         pos: WorldCoords::new(0.5, 0.0, 1.0),
+        corner: LocalVoxelCoords::new(0, 0, 1),
+        axis: 0,
     }
 }
 fn get_node_de() -> VertexNode {
     VertexNode {
         index: 1,
         pos: WorldCoords::new(1.0, 0.0, 0.5),
+        corner: LocalVoxelCoords::new(1, 0, 0),
+        axis: 2,
     }
 }
 fn get_node_ds() -> VertexNode {
     VertexNode {
         index: 2,
         pos: WorldCoords::new(0.5, 0.0, 0.0),
+        corner: LocalVoxelCoords::new(0, 0, 0),
+        axis: 0,
     }
 }
 fn get_node_dw() -> VertexNode {
     VertexNode {
         index: 3,
         pos: WorldCoords::new(0.0, 0.0, 0.5),
+        corner: LocalVoxelCoords::new(0, 0, 0),
+        axis: 2,
     }
 }
 
@@ -59,24 +96,32 @@ fn get_node_un() -> VertexNode {
     VertexNode {
         index: 4,
         pos: WorldCoords::new(0.5, 1.0, 1.0),
+        corner: LocalVoxelCoords::new(0, 1, 1),
+        axis: 0,
     }
 }
 fn get_node_ue() -> VertexNode {
     VertexNode {
         index: 5,
         pos: WorldCoords::new(1.0, 1.0, 0.5),
+        corner: LocalVoxelCoords::new(1, 1, 0),
+        axis: 2,
     }
 }
 fn get_node_us() -> VertexNode {
     VertexNode {
         index: 6,
         pos: WorldCoords::new(0.5, 1.0, 0.0),
+        corner: LocalVoxelCoords::new(0, 1, 0),
+        axis: 0,
     }
 }
 fn get_node_uw() -> VertexNode {
     VertexNode {
         index: 7,
         pos: WorldCoords::new(0.0, 1.0, 0.5),
+        corner: LocalVoxelCoords::new(0, 1, 0),
+        axis: 2,
     }
 }
 
@@ -84,24 +129,32 @@ fn get_node_nw() -> VertexNode {
     VertexNode {
         index: 8,
         pos: WorldCoords::new(0.0, 0.5, 1.0),
+        corner: LocalVoxelCoords::new(0, 0, 1),
+        axis: 1,
     }
 }
 fn get_node_ne() -> VertexNode {
     VertexNode {
         index: 9,
         pos: WorldCoords::new(1.0, 0.5, 1.0),
+        corner: LocalVoxelCoords::new(1, 0, 1),
+        axis: 1,
     }
 }
 fn get_node_se() -> VertexNode {
     VertexNode {
         index: 10,
         pos: WorldCoords::new(1.0, 0.5, 0.0),
+        corner: LocalVoxelCoords::new(1, 0, 0),
+        axis: 1,
     }
 }
 fn get_node_sw() -> VertexNode {
     VertexNode {
         index: 11,
         pos: WorldCoords::new(0.0, 0.5, 0.0),
+        corner: LocalVoxelCoords::new(0, 0, 0),
+        axis: 1,
     }
 }
 
@@ -126,52 +179,50 @@ fn get_base_nodes() -> [VertexNode; NODES_POS_COUNT] {
 type Nodes = [Voxel; NODES_POS_COUNT];
 type VoxelsBlock = [[[Voxel; 2]; 2]; 2];
 
-fn get_voxel_with_field_size(field: &Vec<Voxel>, pos: VoxelCoords, field_size: u32) -> Voxel {
-    let index = pos.z as u32 + pos.y as u32 * field_size + pos.x as u32 * field_size * field_size;
-    field[index as usize]
-}
-
 fn get_voxels_for_vertex(
     field: &Vec<Voxel>,
-    base_pos: VoxelCoords,
-    field_size: u32,
+    base_pos: LocalVoxelCoords,
+    extent: FieldExtent,
 ) -> VoxelsBlock {
     let voxels: [[[Voxel; 2]; 2]; 2] = [
         [
             [
-                get_voxel_with_field_size(field, base_pos + VoxelCoords::new(0, 0, 0), field_size),
-                get_voxel_with_field_size(field, base_pos + VoxelCoords::new(0, 0, 1), field_size),
+                get_voxel(field, base_pos + LocalVoxelCoords::new(0, 0, 0), extent),
+                get_voxel(field, base_pos + LocalVoxelCoords::new(0, 0, 1), extent),
             ],
             [
-                get_voxel_with_field_size(field, base_pos + VoxelCoords::new(0, 1, 0), field_size),
-                get_voxel_with_field_size(field, base_pos + VoxelCoords::new(0, 1, 1), field_size),
+                get_voxel(field, base_pos + LocalVoxelCoords::new(0, 1, 0), extent),
+                get_voxel(field, base_pos + LocalVoxelCoords::new(0, 1, 1), extent),
             ],
         ],
         [
             [
-                get_voxel_with_field_size(field, base_pos + VoxelCoords::new(1, 0, 0), field_size),
-                get_voxel_with_field_size(field, base_pos + VoxelCoords::new(1, 0, 1), field_size),
+                get_voxel(field, base_pos + LocalVoxelCoords::new(1, 0, 0), extent),
+                get_voxel(field, base_pos + LocalVoxelCoords::new(1, 0, 1), extent),
             ],
             [
-                get_voxel_with_field_size(field, base_pos + VoxelCoords::new(1, 1, 0), field_size),
-                get_voxel_with_field_size(field, base_pos + VoxelCoords::new(1, 1, 1), field_size),
+                get_voxel(field, base_pos + LocalVoxelCoords::new(1, 1, 0), extent),
+                get_voxel(field, base_pos + LocalVoxelCoords::new(1, 1, 1), extent),
             ],
         ],
     ];
     voxels
 }
 
+/// The value of the node is the position of the surface along the edge going from a to b,
+/// its material is the one of the solid end of the edge
 fn chose_voxel_for_node(a: Voxel, b: Voxel) -> Voxel {
     if a.value < 0. {
         return Voxel {
-            value: (-a.value) / (b.value - a.value),
-            material: VoxelMaterial::AIR,
+            value: ((-a.value) / (b.value - a.value)).clamp(MIN_EDGE_OFFSET, 1. - MIN_EDGE_OFFSET),
+            material: b.material,
         };
     }
     if b.value < 0. {
         return Voxel {
-            value: 1.0 - (-b.value) / (a.value - b.value),
-            material: VoxelMaterial::AIR,
+            value: (1.0 - (-b.value) / (a.value - b.value))
+                .clamp(MIN_EDGE_OFFSET, 1. - MIN_EDGE_OFFSET),
+            material: a.material,
         };
     }
     Voxel {
@@ -218,9 +269,77 @@ fn shift_node_pos(pos: WorldCoords, value: f32) -> WorldCoords {
     panic!("failed to process pos {:?}", pos);
 }
 
+/// State shared by every cube while marching through a field
+struct MeshContext<'a> {
+    field: &'a Vec<Voxel>,
+    extent: FieldExtent,
+    region: Region,
+    block: &'a mut MeshBlock,
+    // only covers the edges of the region
+    edge_cache: Vec<u32>,
+}
+
+/// Returns the index of the vertex lying on the edge of the given node, generating the vertex
+/// if no neighbouring cube has done it already
+fn get_or_insert_vertex(
+    ctx: &mut MeshContext,
+    pos: LocalVoxelCoords,
+    node: VertexNode,
+    node_voxel: Voxel,
+) -> u32 {
+    let value = node_voxel.value;
+    let edge_base = pos + node.corner;
+    let edge_index = 3 * ctx.extent.index(
+        edge_base.x as usize,
+        edge_base.y as usize,
+        edge_base.z as usize,
+    ) + node.axis;
+
+    // edges of the region go from its min corner to its max corner (included)
+    let size_y = (ctx.region.max.y - ctx.region.min.y + 1) as usize;
+    let size_z = (ctx.region.max.z - ctx.region.min.z + 1) as usize;
+    let cache_index = 3
+        * ((edge_base.z as u32 - ctx.region.min.z) as usize
+            + (edge_base.y as u32 - ctx.region.min.y) as usize * size_z
+            + (edge_base.x as u32 - ctx.region.min.x) as usize * size_y * size_z)
+        + node.axis;
+
+    if ctx.edge_cache[cache_index] != u32::MAX {
+        return ctx.edge_cache[cache_index];
+    }
+
+    let mut edge_end = edge_base;
+    match node.axis {
+        0 => edge_end.x += 1,
+        1 => edge_end.y += 1,
+        _ => edge_end.z += 1,
+    }
+
+    // the density increases towards the inside of the entity, so the normal is opposed to the
+    // gradient, which is interpolated the same way as the vertex position along the edge
+    let gradient = density_gradient(ctx.field, edge_base, ctx.extent) * (1.0 - value)
+        + density_gradient(ctx.field, edge_end, ctx.extent) * value;
+
+    let pos_vec = WorldCoords::new(pos.x as f32, pos.y as f32, pos.z as f32);
+    let vertex_pos = shift_node_pos(node.pos, value) + pos_vec;
+    let normal = -gradient.normalize_or_zero();
+
+    let index = ctx.block.vertices.len() as u32;
+    ctx.block.vertices.push(Vertex {
+        color: Color::srgb(0.3, 0.3, 0.3),
+        normal,
+        pos: vertex_pos,
+        voxel_material: node_voxel.material,
+        occlusion: 1.,
+    });
+    ctx.block.keys.push(edge_index);
+    ctx.edge_cache[cache_index] = index;
+    index
+}
+
 fn append_triangle(
-    pos: VoxelCoords,
-    vertices: &mut Vec<Vertex>,
+    ctx: &mut MeshContext,
+    pos: LocalVoxelCoords,
     nodes: Nodes,
     a: VertexNode,
     b: VertexNode,
@@ -234,70 +353,73 @@ fn append_triangle(
         return;
     }
 
-    let pos_vec = WorldCoords::new(pos.x as f32, pos.y as f32, pos.z as f32);
-
-    let a_pos = shift_node_pos(a.pos, a_v.value) + pos_vec;
-    let b_pos = shift_node_pos(b.pos, b_v.value) + pos_vec;
-    let c_pos = shift_node_pos(c.pos, c_v.value) + pos_vec;
+    let ia = get_or_insert_vertex(ctx, pos, a, a_v);
+    let ib = get_or_insert_vertex(ctx, pos, b, b_v);
+    let ic = get_or_insert_vertex(ctx, pos, c, c_v);
 
-    let normal = (c_pos - a_pos).cross(b_pos - a_pos);
-    let normal = Vec3::new(
-        normal.x.into_inner(),
-        normal.y.into_inner(),
-        normal.z.into_inner(),
-    );
-    let normal = normal.normalize();
+    ctx.block.push_triangle(ic, ib, ia);
+}
 
-    // let sig3 = sigmoid(pos.y as f32, 20.0);
-    let vc = Vertex {
-        color: Color::srgb(0.3, 0.3, 0.3),
-        normal,
-        pos: c_pos,
-        voxel_material: VoxelMaterial::AIR,
-    };
-    let vb = Vertex {
-        color: Color::srgb(0.3, 0.3, 0.3),
-        normal,
-        pos: b_pos,
-        voxel_material: VoxelMaterial::AIR,
-    };
-    let va = Vertex {
-        color: Color::srgb(0.3, 0.3, 0.3),
-        normal,
-        pos: a_pos,
-        voxel_material: VoxelMaterial::AIR,
-    };
+/// Generates the triangles of a cube from the classic marching cubes table
+fn append_cube_triangles(
+    ctx: &mut MeshContext,
+    pos: LocalVoxelCoords,
+    voxels: VoxelsBlock,
+    nodes: Nodes,
+) {
+    let triangle_points = table::TABLE[table::get_index_by_voxels(voxels)];
 
-    vertices.push(vc);
-    vertices.push(vb);
-    vertices.push(va);
-}
+    let mut triangle_offset = 0;
 
-/// Applies marching cubes on a 3d field of Voxels
-/// field_size represents the size of the field along one axis (only squared field allowed)
-/// TODO: Allow using marching cubes on specific regions only
-pub fn find_triangles(vertices: &mut Vec<Vertex>, field: &Vec<Voxel>, field_size: u32) {
-    for x in 0..(field_size - 1) {
-        for y in 0..(field_size - 1) {
-            for z in 0..(field_size - 1) {
-                let pos = VoxelCoords::new(x as u8, y as u8, z as u8);
-                let voxels = get_voxels_for_vertex(field, pos, field_size);
-                let nodes = get_vertex_nodes(voxels);
+    let nodes_arr = get_base_nodes();
 
-                let triangle_points = table::TABLE[table::get_index_by_voxels(voxels)];
+    while triangle_points[triangle_offset] != -1 {
+        let a = nodes_arr[triangle_points[triangle_offset] as usize];
+        let b = nodes_arr[triangle_points[triangle_offset + 1] as usize];
+        let c = nodes_arr[triangle_points[triangle_offset + 2] as usize];
 
-                let mut triangle_offset = 0;
+        append_triangle(ctx, pos, nodes, a, b, c);
 
-                let nodes_arr = get_base_nodes();
+        triangle_offset += 3;
+    }
+}
 
-                while triangle_points[triangle_offset] != -1 {
-                    let a = nodes_arr[triangle_points[triangle_offset] as usize];
-                    let b = nodes_arr[triangle_points[triangle_offset + 1] as usize];
-                    let c = nodes_arr[triangle_points[triangle_offset + 2] as usize];
+/// Applies marching cubes on the cubes of a 3d field of Voxels lying in `region`,
+/// appending the result to `block`
+/// Vertices are shared between adjacent triangles, each triplet of `indices` describes a triangle
+/// Vertex normals are taken from the density gradient of the field
+pub fn find_triangles(
+    block: &mut MeshBlock,
+    field: &Vec<Voxel>,
+    extent: FieldExtent,
+    region: Region,
+    variant: MarchingCubesVariant,
+) {
+    let cache_size = (region.max.x - region.min.x + 1)
+        * (region.max.y - region.min.y + 1)
+        * (region.max.z - region.min.z + 1);
+    let mut ctx = MeshContext {
+        field,
+        extent,
+        region,
+        block,
+        edge_cache: vec![u32::MAX; 3 * cache_size as usize],
+    };
 
-                    append_triangle(pos, vertices, nodes, a, b, c);
+    for x in region.min.x..region.max.x {
+        for y in region.min.y..region.max.y {
+            for z in region.min.z..region.max.z {
+                let pos = LocalVoxelCoords::new(x, y, z);
+                let voxels = get_voxels_for_vertex(field, pos, extent);
+                let nodes = get_vertex_nodes(voxels);
 
-                    triangle_offset += 3;
+                match variant {
+                    MarchingCubesVariant::Classic => {
+                        append_cube_triangles(&mut ctx, pos, voxels, nodes)
+                    }
+                    MarchingCubesVariant::AsymptoticDecider => {
+                        asymptotic_decider::append_cube_polygons(&mut ctx, pos, voxels, nodes)
+                    }
                 }
             }
         }
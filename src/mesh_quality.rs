@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use bevy::math::Vec3;
+
+use crate::common::vertex::Vertex;
+use crate::mesher::DEGENERATE_AREA;
+
+/// Topological problems found in a triangle mesh
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct MeshValidation {
+    pub triangle_count: usize,
+    // edges used by a single triangle, i.e. holes in the surface
+    pub boundary_edges: usize,
+    // edges shared by more than two triangles
+    pub non_manifold_edges: usize,
+    // triangles with a repeated vertex or (almost) no area
+    pub degenerate_triangles: usize,
+}
+
+impl MeshValidation {
+    /// Checks a mesh given as triplets of indices into vertices
+    pub fn validate(vertices: &Vec<Vertex>, indices: &Vec<u32>) -> Self {
+        let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+        let mut degenerate_triangles = 0;
+
+        for triangle in indices.chunks_exact(3) {
+            for k in 0..3 {
+                let a = triangle[k];
+                let b = triangle[(k + 1) % 3];
+                *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+
+            let [a, b, c] =
+                [0, 1, 2].map(|k| Vec3::from(vertices[triangle[k] as usize].pos.into_inners_arr()));
+            let area = 0.5 * (b - a).cross(c - a).length();
+            if triangle[0] == triangle[1]
+                || triangle[1] == triangle[2]
+                || triangle[2] == triangle[0]
+                || area < DEGENERATE_AREA
+            {
+                degenerate_triangles += 1;
+            }
+        }
+
+        Self {
+            triangle_count: indices.len() / 3,
+            boundary_edges: edges.values().filter(|&&count| count == 1).count(),
+            non_manifold_edges: edges.values().filter(|&&count| count > 2).count(),
+            degenerate_triangles,
+        }
+    }
+
+    /// A closed 2-manifold has every edge shared by exactly two triangles
+    pub fn is_closed_manifold(&self) -> bool {
+        self.boundary_edges == 0 && self.non_manifold_edges == 0
+    }
+}
+
+impl fmt::Display for MeshValidation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} triangles, {} boundary edges, {} non-manifold edges, {} degenerate triangles",
+            self.triangle_count,
+            self.boundary_edges,
+            self.non_manifold_edges,
+            self.degenerate_triangles
+        )
+    }
+}
+
+/// Size of the mesh of an entity and time spent generating it
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct MeshStats {
+    pub triangle_count: usize,
+    pub vertex_count: usize,
+    // triangles dropped by the mesher because they had no area, or merged into their neighbours
+    pub degenerate_triangles: usize,
+    pub surface_area: f32,
+    // bounding box of the vertices, in voxels
+    pub bounds_min: Vec3,
+    pub bounds_max: Vec3,
+    pub remeshed_blocks: usize,
+    pub voxel_generation_time: Duration,
+    pub meshing_time: Duration,
+    // time spent cropping or growing the voxel field
+    pub resize_time: Duration,
+    pub lod_meshing_time: Duration,
+    pub simplification_time: Duration,
+}
+
+impl MeshStats {
+    /// Updates the geometric part of the stats from a mesh
+    pub fn measure(&mut self, vertices: &Vec<Vertex>, indices: &Vec<u32>) {
+        let positions: Vec<Vec3> = vertices
+            .iter()
+            .map(|v| Vec3::from(v.pos.into_inners_arr()))
+            .collect();
+
+        self.triangle_count = indices.len() / 3;
+        self.vertex_count = vertices.len();
+        self.surface_area = indices
+            .chunks_exact(3)
+            .map(|t| {
+                let [a, b, c] = [t[0], t[1], t[2]].map(|i| positions[i as usize]);
+                0.5 * (b - a).cross(c - a).length()
+            })
+            .sum();
+        (self.bounds_min, self.bounds_max) = if positions.is_empty() {
+            (Vec3::ZERO, Vec3::ZERO)
+        } else {
+            positions.iter().fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), &p| (min.min(p), max.max(p)),
+            )
+        };
+    }
+}
+
+impl fmt::Display for MeshStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} triangles, {} vertices, {} degenerate triangles dropped",
+            self.triangle_count, self.vertex_count, self.degenerate_triangles
+        )?;
+        writeln!(
+            f,
+            "surface area {:.2}, bounds {} to {}",
+            self.surface_area, self.bounds_min, self.bounds_max
+        )?;
+        write!(
+            f,
+            "voxels {:?}, meshing {:?} ({} blocks), resize {:?}, lod {:?}, simplification {:?}",
+            self.voxel_generation_time,
+            self.meshing_time,
+            self.remeshed_blocks,
+            self.resize_time,
+            self.lod_meshing_time,
+            self.simplification_time
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::Vec3;
+
+    use crate::common::field_extent::FieldExtent;
+    use crate::common::voxel_material::VoxelMaterial;
+    use crate::common::voxels::Voxel;
+    use crate::procedural_entity::ProceduralEntity;
+    use crate::sdf::SdfNode;
+
+    /// Entity whose voxels take the given values, the ones on the border of the field being
+    /// empty so that the surface is closed
+    fn entity_from_values(
+        extent: FieldExtent,
+        value: impl Fn(usize, usize, usize) -> f32,
+    ) -> ProceduralEntity {
+        let mut entity = ProceduralEntity::new(extent);
+        entity.voxel_field = (0..extent.volume())
+            .map(|i| {
+                let c = extent.coords(i);
+                let border = [(c.x, extent.x), (c.y, extent.y), (c.z, extent.z)]
+                    .iter()
+                    .any(|&(c, size)| c == 0 || c + 1 == size);
+                Voxel {
+                    value: if border { -1. } else { value(c.x, c.y, c.z) },
+                    material: VoxelMaterial::STONE,
+                }
+            })
+            .collect();
+        entity
+    }
+
+    fn assert_closed(mut entity: ProceduralEntity, name: &str) {
+        entity.generate_vertices();
+        let report = entity.validate_mesh();
+        assert!(report.triangle_count > 0, "{}: no triangles", name);
+        assert!(report.is_closed_manifold(), "{}: {}", name, report);
+    }
+
+    #[test]
+    fn sphere_mesh_is_closed() {
+        let sphere = SdfNode::sphere(Vec3::splat(15.5), 9.3);
+        assert_closed(
+            ProceduralEntity::from_sdf(&sphere, FieldExtent::cube(32)),
+            "sphere",
+        );
+    }
+
+    #[test]
+    fn saddle_mesh_is_closed() {
+        // every face of the cube between (3, 3, 3) and (4, 4, 4) has two solid corners on one
+        // diagonal and two empty ones on the other, joined or separated by their values
+        let solid = [(3, 3, 3), (4, 4, 3), (4, 3, 4), (3, 4, 4)];
+        for (inside, outside) in [(1., -0.1), (0.1, -1.), (0.5, -0.4), (0.4, -0.5)] {
+            let entity = entity_from_values(FieldExtent::cube(8), |x, y, z| {
+                if solid.contains(&(x, y, z)) {
+                    inside
+                } else if (3..=4).contains(&x) && (3..=4).contains(&y) && (3..=4).contains(&z) {
+                    outside
+                } else {
+                    -1.
+                }
+            });
+            assert_closed(entity, &format!("saddle {} {}", inside, outside));
+        }
+
+        // pseudo random values, with many ambiguous faces
+        let mut seed = 12345u32;
+        let values: Vec<f32> = (0..16 * 16 * 16)
+            .map(|_| {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                (seed >> 8) as f32 / (1 << 23) as f32 - 1.
+            })
+            .collect();
+        let entity = entity_from_values(FieldExtent::cube(16), |x, y, z| {
+            values[z + 16 * (y + 16 * x)]
+        });
+        assert_closed(entity, "random");
+    }
+}
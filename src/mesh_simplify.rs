@@ -0,0 +1,367 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use bevy::math::{DMat3, DVec3, Vec3};
+
+use crate::common::coords::WorldCoords;
+use crate::common::vertex::Vertex;
+
+/// Triangles with a cross product shorter than this have no usable normal
+const DEGENERATE_NORMAL: f64 = 1e-12;
+
+/// Limits of the quadric error metrics simplification
+/// Edges are collapsed cheapest first, until the mesh has target_triangles triangles or the
+/// cheapest collapse costs more than max_error
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SimplifySettings {
+    pub target_triangles: Option<usize>,
+    // sum of the squared distances (in voxels) between a collapsed vertex and the planes of the
+    // original triangles around it
+    pub max_error: f32,
+}
+
+impl Default for SimplifySettings {
+    fn default() -> Self {
+        Self {
+            target_triangles: None,
+            max_error: 0.01,
+        }
+    }
+}
+
+/// Symmetric 4x4 matrix measuring the squared distance of a point to a set of planes
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: DVec3, point: DVec3) -> Self {
+        let (a, b, c) = (normal.x, normal.y, normal.z);
+        let d = -normal.dot(point);
+        Self([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+    }
+
+    fn add(&self, other: &Quadric) -> Self {
+        let mut sum = *self;
+        for (s, o) in sum.0.iter_mut().zip(other.0.iter()) {
+            *s += o;
+        }
+        sum
+    }
+
+    fn error(&self, p: DVec3) -> f64 {
+        let q = &self.0;
+        q[0] * p.x * p.x
+            + 2. * q[1] * p.x * p.y
+            + 2. * q[2] * p.x * p.z
+            + 2. * q[3] * p.x
+            + q[4] * p.y * p.y
+            + 2. * q[5] * p.y * p.z
+            + 2. * q[6] * p.y
+            + q[7] * p.z * p.z
+            + 2. * q[8] * p.z
+            + q[9]
+    }
+
+    /// Point minimizing the error, if the quadric is not (close to) singular
+    fn optimal_point(&self) -> Option<DVec3> {
+        let q = &self.0;
+        let a = DMat3::from_cols_array(&[q[0], q[1], q[2], q[1], q[4], q[5], q[2], q[5], q[7]]);
+        if a.determinant().abs() < 1e-9 {
+            return None;
+        }
+        Some(a.inverse() * -DVec3::new(q[3], q[6], q[8]))
+    }
+}
+
+/// Edge collapse waiting in the queue, only valid while both vertices keep their version
+struct Collapse {
+    cost: f64,
+    keep: u32,
+    remove: u32,
+    pos: DVec3,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // reversed so that the binary heap pops the cheapest collapse first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| (other.keep, other.remove).cmp(&(self.keep, self.remove)))
+    }
+}
+
+struct Simplifier<'a> {
+    vertices: &'a Vec<Vertex>,
+    positions: Vec<DVec3>,
+    normals: Vec<Vec3>,
+    quadrics: Vec<Quadric>,
+    // vertices lying on a material boundary or on a hole of the mesh, which never move
+    locked: Vec<bool>,
+    versions: Vec<u32>,
+    triangles: Vec<[u32; 3]>,
+    alive: Vec<bool>,
+    vertex_triangles: Vec<Vec<usize>>,
+    heap: BinaryHeap<Collapse>,
+}
+
+fn triangle_normal(a: DVec3, b: DVec3, c: DVec3) -> DVec3 {
+    (b - a).cross(c - a)
+}
+
+impl<'a> Simplifier<'a> {
+    fn new(vertices: &'a Vec<Vertex>, indices: &Vec<u32>) -> Self {
+        let positions: Vec<DVec3> = vertices
+            .iter()
+            .map(|v| Vec3::from(v.pos.into_inners_arr()).as_dvec3())
+            .collect();
+        let triangles: Vec<[u32; 3]> = indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect();
+
+        let mut quadrics = vec![Quadric::default(); vertices.len()];
+        let mut vertex_triangles = vec![Vec::new(); vertices.len()];
+        let mut edge_uses: HashMap<(u32, u32), usize> = HashMap::new();
+        for (i, t) in triangles.iter().enumerate() {
+            let [a, b, c] = t.map(|v| positions[v as usize]);
+            let normal = triangle_normal(a, b, c);
+            let quadric = if normal.length_squared() > DEGENERATE_NORMAL {
+                Quadric::from_plane(normal.normalize(), a)
+            } else {
+                Quadric::default()
+            };
+            for k in 0..3 {
+                quadrics[t[k] as usize] = quadrics[t[k] as usize].add(&quadric);
+                vertex_triangles[t[k] as usize].push(i);
+                let (u, v) = (t[k], t[(k + 1) % 3]);
+                *edge_uses.entry((u.min(v), u.max(v))).or_default() += 1;
+            }
+        }
+
+        let mut locked = vec![false; vertices.len()];
+        for (&(u, v), &uses) in edge_uses.iter() {
+            if uses != 2
+                || vertices[u as usize].voxel_material != vertices[v as usize].voxel_material
+            {
+                locked[u as usize] = true;
+                locked[v as usize] = true;
+            }
+        }
+
+        let mut simplifier = Self {
+            vertices,
+            positions,
+            normals: vertices.iter().map(|v| v.normal).collect(),
+            quadrics,
+            locked,
+            versions: vec![0; vertices.len()],
+            alive: vec![true; triangles.len()],
+            triangles,
+            vertex_triangles,
+            heap: BinaryHeap::new(),
+        };
+        for &(u, v) in edge_uses.keys() {
+            simplifier.push_collapse(u, v);
+        }
+        simplifier
+    }
+
+    fn push_collapse(&mut self, u: u32, v: u32) {
+        let (ui, vi) = (u as usize, v as usize);
+        let (keep, remove) = match (self.locked[ui], self.locked[vi]) {
+            (true, true) => return,
+            (false, true) => (v, u),
+            _ => (u, v),
+        };
+        let quadric = self.quadrics[ui].add(&self.quadrics[vi]);
+
+        let (pu, pv) = (self.positions[ui], self.positions[vi]);
+        let pos = if self.locked[keep as usize] {
+            self.positions[keep as usize]
+        } else {
+            let mid = (pu + pv) / 2.;
+            // the optimal point of an almost flat area can be far away from the edge
+            let optimal = quadric
+                .optimal_point()
+                .filter(|p| p.distance(mid) <= pu.distance(pv));
+            optimal.unwrap_or_else(|| {
+                [pu, pv, mid]
+                    .into_iter()
+                    .min_by(|a, b| quadric.error(*a).total_cmp(&quadric.error(*b)))
+                    .unwrap()
+            })
+        };
+
+        self.heap.push(Collapse {
+            cost: quadric.error(pos).max(0.),
+            keep,
+            remove,
+            pos,
+            versions: (self.versions[keep as usize], self.versions[remove as usize]),
+        });
+    }
+
+    fn neighbours(&self, v: u32) -> HashSet<u32> {
+        self.vertex_triangles[v as usize]
+            .iter()
+            .flat_map(|&t| self.triangles[t])
+            .filter(|&n| n != v)
+            .collect()
+    }
+
+    /// Whether collapsing the edge keeps the mesh manifold and does not flip any triangle
+    fn can_collapse(&self, keep: u32, remove: u32, pos: DVec3) -> bool {
+        let shared = self.vertex_triangles[keep as usize]
+            .iter()
+            .filter(|&&t| self.triangles[t].contains(&remove))
+            .count();
+        let common = self
+            .neighbours(keep)
+            .intersection(&self.neighbours(remove))
+            .count();
+        // the link condition: the only vertices around both ends are the ones of the triangles
+        // sharing the edge
+        if shared == 0 || common != shared {
+            return false;
+        }
+
+        self.vertex_triangles[keep as usize]
+            .iter()
+            .chain(self.vertex_triangles[remove as usize].iter())
+            .filter(|&&t| {
+                !(self.triangles[t].contains(&keep) && self.triangles[t].contains(&remove))
+            })
+            .all(|&t| {
+                let before = self.triangles[t].map(|v| self.positions[v as usize]);
+                let after = self.triangles[t].map(|v| {
+                    if v == keep || v == remove {
+                        pos
+                    } else {
+                        self.positions[v as usize]
+                    }
+                });
+                let old_normal = triangle_normal(before[0], before[1], before[2]);
+                let new_normal = triangle_normal(after[0], after[1], after[2]);
+                old_normal.length_squared() <= DEGENERATE_NORMAL || old_normal.dot(new_normal) > 0.
+            })
+    }
+
+    fn collapse(&mut self, keep: u32, remove: u32, pos: DVec3) -> usize {
+        let (ki, ri) = (keep as usize, remove as usize);
+        self.positions[ki] = pos;
+        self.quadrics[ki] = self.quadrics[ki].add(&self.quadrics[ri]);
+        self.normals[ki] = (self.normals[ki] + self.normals[ri]).normalize_or(self.normals[ki]);
+        self.versions[ki] += 1;
+        self.versions[ri] += 1;
+
+        let mut removed_triangles = 0;
+        for t in std::mem::take(&mut self.vertex_triangles[ri]) {
+            if self.triangles[t].contains(&keep) {
+                self.alive[t] = false;
+                removed_triangles += 1;
+            } else {
+                for v in self.triangles[t].iter_mut() {
+                    if *v == remove {
+                        *v = keep;
+                    }
+                }
+                self.vertex_triangles[ki].push(t);
+            }
+        }
+        // drop the dead triangles from the vertices around them
+        for n in self.neighbours(keep).into_iter().chain([keep]) {
+            let alive = &self.alive;
+            self.vertex_triangles[n as usize].retain(|&t| alive[t]);
+        }
+
+        for n in self.neighbours(keep) {
+            self.push_collapse(keep, n);
+        }
+        removed_triangles
+    }
+
+    fn run(&mut self, settings: SimplifySettings) {
+        let target = settings.target_triangles.unwrap_or(0);
+        let mut triangle_count = self.triangles.len();
+        while triangle_count > target {
+            let Some(collapse) = self.heap.pop() else {
+                break;
+            };
+            if collapse.cost > settings.max_error as f64 {
+                break;
+            }
+            let (keep, remove) = (collapse.keep, collapse.remove);
+            if collapse.versions != (self.versions[keep as usize], self.versions[remove as usize])
+                || !self.can_collapse(keep, remove, collapse.pos)
+            {
+                continue;
+            }
+            triangle_count -= self.collapse(keep, remove, collapse.pos);
+        }
+    }
+
+    fn output(&self) -> (Vec<Vertex>, Vec<u32>) {
+        let mut remap = vec![u32::MAX; self.vertices.len()];
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for (t, triangle) in self.triangles.iter().enumerate() {
+            if !self.alive[t] {
+                continue;
+            }
+            for &v in triangle.iter() {
+                let v = v as usize;
+                if remap[v] == u32::MAX {
+                    remap[v] = vertices.len() as u32;
+                    let pos = self.positions[v].as_vec3();
+                    vertices.push(Vertex {
+                        pos: WorldCoords::new(pos.x, pos.y, pos.z),
+                        normal: self.normals[v],
+                        ..self.vertices[v].clone()
+                    });
+                }
+                indices.push(remap[v]);
+            }
+        }
+        (vertices, indices)
+    }
+}
+
+/// Simplifies a mesh by collapsing edges in order of their quadric error
+/// Vertices between two materials (and on the holes of the mesh) are never moved, so material
+/// boundaries are kept as they are
+pub fn simplify(
+    vertices: &Vec<Vertex>,
+    indices: &Vec<u32>,
+    settings: SimplifySettings,
+) -> (Vec<Vertex>, Vec<u32>) {
+    let mut simplifier = Simplifier::new(vertices, indices);
+    simplifier.run(settings);
+    simplifier.output()
+}
@@ -0,0 +1,90 @@
+use std::sync::LazyLock;
+
+use bevy::math::Vec3;
+
+use crate::common::field_extent::FieldExtent;
+use crate::common::voxels::Voxel;
+use crate::mesher::{sample_density, MeshBlock};
+
+/// Number of directions sampled around each vertex (about half of them are in its hemisphere)
+const DIRECTION_COUNT: usize = 32;
+/// Distances of the samples along each direction, as fractions of the radius
+const SAMPLE_DISTANCES: [f32; 2] = [0.5, 1.0];
+
+/// Directions evenly spread on the unit sphere (Fibonacci lattice)
+static DIRECTIONS: LazyLock<[Vec3; DIRECTION_COUNT]> = LazyLock::new(|| {
+    let golden_angle = std::f32::consts::PI * (3. - 5f32.sqrt());
+    std::array::from_fn(|i| {
+        let y = 1. - 2. * (i as f32 + 0.5) / DIRECTION_COUNT as f32;
+        let r = (1. - y * y).sqrt();
+        let theta = golden_angle * i as f32;
+        Vec3::new(r * theta.cos(), y, r * theta.sin())
+    })
+});
+
+/// Ambient occlusion baked into the vertices from the density of the voxel field around them
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AmbientOcclusion {
+    // distance (in voxels) up to which the field is sampled around each vertex
+    pub radius: f32,
+    // how dark a fully occluded vertex gets, between 0 and 1
+    pub strength: f32,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        Self {
+            radius: 3.,
+            strength: 0.8,
+        }
+    }
+}
+
+/// Light reaching a vertex (1 for fully exposed), from the amount of matter in the hemisphere
+/// around its normal
+fn vertex_occlusion(
+    field: &Vec<Voxel>,
+    extent: FieldExtent,
+    pos: Vec3,
+    normal: Vec3,
+    settings: AmbientOcclusion,
+) -> f32 {
+    let mut occlusion = 0.;
+    let mut total_weight = 0.;
+    for &direction in DIRECTIONS.iter() {
+        // samples close to the tangent plane mostly hit the surface the vertex lies on
+        let weight = direction.dot(normal);
+        if weight <= 0.1 {
+            continue;
+        }
+        for distance in SAMPLE_DISTANCES {
+            let density =
+                sample_density(field, extent, pos + direction * settings.radius * distance);
+            occlusion += weight * (density + 0.5).clamp(0., 1.);
+            total_weight += weight;
+        }
+    }
+    if total_weight == 0. {
+        return 1.;
+    }
+    1. - settings.strength * occlusion / total_weight
+}
+
+/// Computes the occlusion of every vertex of a block
+/// Vertices only depend on the field around them, so blocks that were not remeshed keep theirs
+pub fn bake_occlusion(
+    block: &mut MeshBlock,
+    field: &Vec<Voxel>,
+    extent: FieldExtent,
+    settings: AmbientOcclusion,
+) {
+    for vertex in block.vertices.iter_mut() {
+        vertex.occlusion = vertex_occlusion(
+            field,
+            extent,
+            Vec3::from(vertex.pos.into_inners_arr()),
+            vertex.normal,
+            settings,
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use bevy::math::Vec3;
+use bevy::prelude::Color;
+
+use crate::common::coords::{LocalVoxelCoords, WorldCoords};
+use crate::common::field_extent::FieldExtent;
+use crate::common::region::Region;
+use crate::common::vertex::Vertex;
+use crate::common::voxel_material::VoxelMaterial;
+use crate::common::voxels::Voxel;
+use crate::mesher::{get_voxel, MeshBlock, Mesher, UNSHARED_VERTEX};
+
+// Each solid voxel is a cube of side 1 centred on the voxel. A block meshes the faces of the
+// voxels of its region, the regions of the blocks being given as cubes, the last voxels of the
+// field are meshed by the blocks ending on the border of the field.
+// Faces are merged greedily: in each slice of the region, rectangles of visible faces with the
+// same material are grown along the first axis of the slice, then along the second one.
+
+/// Each voxel with a value of at least 0 is rendered as a solid cube, with the coplanar faces
+/// of a same material merged into large quads
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Blocky;
+
+fn solid(field: &Vec<Voxel>, extent: FieldExtent, pos: [i64; 3]) -> bool {
+    let size = [extent.x, extent.y, extent.z];
+    if (0..3).any(|a| pos[a] < 0 || pos[a] >= size[a] as i64) {
+        // the outside of the field is air
+        return false;
+    }
+    let pos = LocalVoxelCoords::new(pos[0] as u32, pos[1] as u32, pos[2] as u32);
+    get_voxel(field, pos, extent).value >= 0.
+}
+
+/// Voxels owned by the block of a region, as [min, max) along each axis
+fn voxel_range(extent: FieldExtent, region: Region) -> ([i64; 3], [i64; 3]) {
+    let size = [extent.x, extent.y, extent.z].map(|s| s as i64);
+    let min = [region.min.x, region.min.y, region.min.z].map(|v| v as i64);
+    let max = [region.max.x, region.max.y, region.max.z].map(|v| v as i64);
+    let max = [0, 1, 2].map(|a| {
+        if max[a] >= size[a] - 1 {
+            size[a]
+        } else {
+            max[a]
+        }
+    });
+    (min, max)
+}
+
+/// Adds a quad with the given corner, sides and outward normal
+fn push_quad(
+    block: &mut MeshBlock,
+    corner: Vec3,
+    side_u: Vec3,
+    side_w: Vec3,
+    normal: Vec3,
+    material: VoxelMaterial,
+) {
+    let first = block.vertices.len() as u32;
+    for pos in [
+        corner,
+        corner + side_u,
+        corner + side_u + side_w,
+        corner + side_w,
+    ] {
+        block.vertices.push(Vertex {
+            pos: WorldCoords::new(pos.x, pos.y, pos.z),
+            normal,
+            color: Color::srgb(0.3, 0.3, 0.3),
+            voxel_material: material,
+            occlusion: 1.,
+        });
+        block.keys.push(UNSHARED_VERTEX);
+    }
+    // side_u x side_w has the direction of the normal when the quad is seen from its front
+    if side_u.cross(side_w).dot(normal) > 0. {
+        block.push_triangle(first, first + 1, first + 2);
+        block.push_triangle(first, first + 2, first + 3);
+    } else {
+        block.push_triangle(first, first + 2, first + 1);
+        block.push_triangle(first, first + 3, first + 2);
+    }
+}
+
+/// Covers the faces of a slice with rectangles of a same material, given as their first cell,
+/// their size along each axis of the slice and their material
+fn greedy_rectangles(
+    mut mask: Vec<Option<VoxelMaterial>>,
+    size_u: usize,
+    size_w: usize,
+) -> Vec<(usize, usize, usize, usize, VoxelMaterial)> {
+    let mut rectangles = Vec::new();
+    for i in 0..size_u {
+        let mut j = 0;
+        while j < size_w {
+            let Some(material) = mask[i * size_w + j] else {
+                j += 1;
+                continue;
+            };
+            let same = |i: usize, j: usize| mask[i * size_w + j] == Some(material);
+            let mut width = 1;
+            while j + width < size_w && same(i, j + width) {
+                width += 1;
+            }
+            let mut height = 1;
+            while i + height < size_u && (j..j + width).all(|k| same(i + height, k)) {
+                height += 1;
+            }
+
+            for di in 0..height {
+                for dj in 0..width {
+                    mask[(i + di) * size_w + j + dj] = None;
+                }
+            }
+            rectangles.push((i, j, height, width, material));
+            j += width;
+        }
+    }
+    rectangles
+}
+
+impl Mesher for Blocky {
+    fn mesh_region(
+        &self,
+        block: &mut MeshBlock,
+        field: &Vec<Voxel>,
+        extent: FieldExtent,
+        region: Region,
+    ) {
+        let (min, max) = voxel_range(extent, region);
+        for axis in 0..3 {
+            let (u, w) = ((axis + 1) % 3, (axis + 2) % 3);
+            let (size_u, size_w) = ((max[u] - min[u]) as usize, (max[w] - min[w]) as usize);
+            for direction in [-1, 1] {
+                for slice in min[axis]..max[axis] {
+                    // material of the visible face of each voxel of the slice
+                    let mut mask: Vec<Option<VoxelMaterial>> = vec![None; size_u * size_w];
+                    for i in 0..size_u {
+                        for j in 0..size_w {
+                            let mut pos = [0; 3];
+                            pos[axis] = slice;
+                            pos[u] = min[u] + i as i64;
+                            pos[w] = min[w] + j as i64;
+                            let mut neighbour = pos;
+                            neighbour[axis] += direction;
+                            if solid(field, extent, pos) && !solid(field, extent, neighbour) {
+                                let voxel = LocalVoxelCoords::new(
+                                    pos[0] as u32,
+                                    pos[1] as u32,
+                                    pos[2] as u32,
+                                );
+                                mask[i * size_w + j] =
+                                    Some(get_voxel(field, voxel, extent).material);
+                            }
+                        }
+                    }
+
+                    for (i, j, height, width, material) in greedy_rectangles(mask, size_u, size_w) {
+                        let mut corner = Vec3::ZERO;
+                        corner[axis] = slice as f32 + 0.5 * direction as f32;
+                        corner[u] = (min[u] + i as i64) as f32 - 0.5;
+                        corner[w] = (min[w] + j as i64) as f32 - 0.5;
+                        let mut side_u = Vec3::ZERO;
+                        side_u[u] = height as f32;
+                        let mut side_w = Vec3::ZERO;
+                        side_w[w] = width as f32;
+                        let mut normal = Vec3::ZERO;
+                        normal[axis] = direction as f32;
+                        push_quad(block, corner, side_u, side_w, normal, material);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Splits the solid voxels of a field into boxes, as their min and max corners
+/// Boxes are grown greedily along z, then y, then x, ignoring materials
+pub fn solid_boxes(field: &Vec<Voxel>, extent: FieldExtent) -> Vec<(Vec3, Vec3)> {
+    let mut covered = vec![false; field.len()];
+    let free = |covered: &Vec<bool>, x: usize, y: usize, z: usize| {
+        let index = extent.index(x, y, z);
+        !covered[index] && field[index].value >= 0.
+    };
+
+    let mut boxes = Vec::new();
+    for x in 0..extent.x {
+        for y in 0..extent.y {
+            for z in 0..extent.z {
+                if !free(&covered, x, y, z) {
+                    continue;
+                }
+                let mut size_z = 1;
+                while z + size_z < extent.z && free(&covered, x, y, z + size_z) {
+                    size_z += 1;
+                }
+                let mut size_y = 1;
+                while y + size_y < extent.y
+                    && (z..z + size_z).all(|k| free(&covered, x, y + size_y, k))
+                {
+                    size_y += 1;
+                }
+                let mut size_x = 1;
+                while x + size_x < extent.x
+                    && (y..y + size_y)
+                        .all(|j| (z..z + size_z).all(|k| free(&covered, x + size_x, j, k)))
+                {
+                    size_x += 1;
+                }
+
+                for i in x..x + size_x {
+                    for j in y..y + size_y {
+                        for k in z..z + size_z {
+                            covered[extent.index(i, j, k)] = true;
+                        }
+                    }
+                }
+                let min = Vec3::new(x as f32, y as f32, z as f32) - Vec3::splat(0.5);
+                boxes.push((
+                    min,
+                    min + Vec3::new(size_x as f32, size_y as f32, size_z as f32),
+                ));
+            }
+        }
+    }
+    boxes
+}
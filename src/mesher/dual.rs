@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use bevy::math::Vec3;
+use bevy::prelude::Color;
+
+use crate::common::coords::{LocalVoxelCoords, WorldCoords};
+use crate::common::field_extent::FieldExtent;
+use crate::common::region::Region;
+use crate::common::vertex::Vertex;
+use crate::common::voxel_material::VoxelMaterial;
+use crate::common::voxels::Voxel;
+use crate::mesher::{density_gradient, get_voxel, MeshBlock, MIN_EDGE_OFFSET};
+
+// Dual meshers generate one vertex inside each cube crossed by the surface, and one quad for
+// each edge of the grid crossed by the surface, joining the vertices of the 4 cubes around it.
+// A block generates the quads of the edges starting from its cubes, the vertices of the cubes
+// around them that lie in neighbouring blocks are generated again and welded by the index of
+// their cube.
+
+/// Point where the surface crosses an edge of a cube
+pub(super) struct Crossing {
+    pub pos: Vec3,
+    pub normal: Vec3,
+    // material of the solid end of the edge
+    pub material: VoxelMaterial,
+}
+
+fn unit(axis: usize) -> LocalVoxelCoords {
+    match axis {
+        0 => LocalVoxelCoords::new(1, 0, 0),
+        1 => LocalVoxelCoords::new(0, 1, 0),
+        _ => LocalVoxelCoords::new(0, 0, 1),
+    }
+}
+
+fn component(c: LocalVoxelCoords, axis: usize) -> u32 {
+    match axis {
+        0 => c.x,
+        1 => c.y,
+        _ => c.z,
+    }
+}
+
+/// Previous voxel along an axis
+fn previous(c: LocalVoxelCoords, axis: usize) -> LocalVoxelCoords {
+    match axis {
+        0 => LocalVoxelCoords::new(c.x - 1, c.y, c.z),
+        1 => LocalVoxelCoords::new(c.x, c.y - 1, c.z),
+        _ => LocalVoxelCoords::new(c.x, c.y, c.z - 1),
+    }
+}
+
+fn to_vec3(c: LocalVoxelCoords) -> Vec3 {
+    Vec3::new(c.x as f32, c.y as f32, c.z as f32)
+}
+
+/// Points where the surface crosses the 12 edges of the cube with the given base voxel
+fn cube_crossings(
+    field: &Vec<Voxel>,
+    extent: FieldExtent,
+    base: LocalVoxelCoords,
+) -> Vec<Crossing> {
+    let mut crossings = Vec::new();
+    for axis in 0..3 {
+        let (u, w) = (unit((axis + 1) % 3), unit((axis + 2) % 3));
+        for start in [base, base + u, base + w, base + u + w] {
+            let end = start + unit(axis);
+            let (a, b) = (
+                get_voxel(field, start, extent),
+                get_voxel(field, end, extent),
+            );
+            if (a.value >= 0.) == (b.value >= 0.) {
+                continue;
+            }
+            let t = (a.value / (a.value - b.value)).clamp(MIN_EDGE_OFFSET, 1. - MIN_EDGE_OFFSET);
+            let gradient = density_gradient(field, start, extent) * (1. - t)
+                + density_gradient(field, end, extent) * t;
+            crossings.push(Crossing {
+                pos: to_vec3(start).lerp(to_vec3(end), t),
+                // the density increases towards the inside of the entity
+                normal: -gradient.normalize_or_zero(),
+                material: if a.value >= 0. {
+                    a.material
+                } else {
+                    b.material
+                },
+            });
+        }
+    }
+    crossings
+}
+
+/// Average of the crossings of a cube
+pub(super) fn mass_point(crossings: &[Crossing]) -> Vec3 {
+    crossings.iter().map(|c| c.pos).sum::<Vec3>() / crossings.len().max(1) as f32
+}
+
+/// Most common material among the crossings of a cube
+fn dominant_material(crossings: &[Crossing]) -> VoxelMaterial {
+    crossings
+        .iter()
+        .map(|c| c.material)
+        .max_by_key(|&m| crossings.iter().filter(|c| c.material == m).count())
+        .unwrap_or(VoxelMaterial::AIR)
+}
+
+/// Generates the vertices and quads of the cubes of region, place_vertex giving the position
+/// of the vertex of a cube (from its base voxel and the crossings of its edges)
+pub(super) fn mesh_dual(
+    block: &mut MeshBlock,
+    field: &Vec<Voxel>,
+    extent: FieldExtent,
+    region: Region,
+    place_vertex: impl Fn(Vec3, &[Crossing]) -> Vec3,
+) {
+    // index of the vertex of each cube, by index of the cube in the field
+    let mut cube_vertices: HashMap<usize, u32> = HashMap::new();
+    let mut cube_vertex = |block: &mut MeshBlock, cube: LocalVoxelCoords| -> u32 {
+        let key = extent.index(cube.x as usize, cube.y as usize, cube.z as usize);
+        *cube_vertices.entry(key).or_insert_with(|| {
+            let crossings = cube_crossings(field, extent, cube);
+            let pos = place_vertex(to_vec3(cube), &crossings);
+            let normal = crossings.iter().map(|c| c.normal).sum::<Vec3>();
+            block.vertices.push(Vertex {
+                pos: WorldCoords::new(pos.x, pos.y, pos.z),
+                normal: normal.normalize_or_zero(),
+                color: Color::srgb(0.3, 0.3, 0.3),
+                voxel_material: dominant_material(&crossings),
+                occlusion: 1.,
+            });
+            block.keys.push(key);
+            block.vertices.len() as u32 - 1
+        })
+    };
+
+    for x in region.min.x..region.max.x {
+        for y in region.min.y..region.max.y {
+            for z in region.min.z..region.max.z {
+                let pos = LocalVoxelCoords::new(x, y, z);
+                let solid = get_voxel(field, pos, extent).value >= 0.;
+                for axis in 0..3 {
+                    let (u, w) = ((axis + 1) % 3, (axis + 2) % 3);
+                    // the cubes before the edge lie outside of the field
+                    if component(pos, u) == 0 || component(pos, w) == 0 {
+                        continue;
+                    }
+                    if solid == (get_voxel(field, pos + unit(axis), extent).value >= 0.) {
+                        continue;
+                    }
+
+                    // counterclockwise around the edge when looking from its end
+                    let cubes = [
+                        previous(previous(pos, u), w),
+                        previous(pos, w),
+                        pos,
+                        previous(pos, u),
+                    ];
+                    let mut quad = cubes.map(|cube| cube_vertex(block, cube));
+                    // faces point towards the air
+                    if !solid {
+                        quad.reverse();
+                    }
+
+                    // split the quad along its shortest diagonal
+                    let p =
+                        quad.map(|i| Vec3::from(block.vertices[i as usize].pos.into_inners_arr()));
+                    if p[0].distance_squared(p[2]) <= p[1].distance_squared(p[3]) {
+                        block.push_triangle(quad[0], quad[1], quad[2]);
+                        block.push_triangle(quad[0], quad[2], quad[3]);
+                    } else {
+                        block.push_triangle(quad[0], quad[1], quad[3]);
+                        block.push_triangle(quad[1], quad[2], quad[3]);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use bevy::math::{Mat3, Vec3};
+
+use crate::common::field_extent::FieldExtent;
+use crate::common::region::Region;
+use crate::common::voxels::Voxel;
+use crate::mesher::dual::{mass_point, mesh_dual, Crossing};
+use crate::mesher::{MeshBlock, Mesher};
+
+/// Dual contouring: the vertex of each cube minimizes the quadratic error function (QEF), the
+/// sum of the squared distances to the planes tangent to the surface where it crosses the edges
+/// of the cube, so that edges and corners of the surface stay sharp
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DualContouring {
+    // pulls the vertex towards the average of the crossings, which keeps the QEF solvable on
+    // flat and cylindrical areas
+    pub bias: f32,
+}
+
+impl Default for DualContouring {
+    fn default() -> Self {
+        Self { bias: 0.05 }
+    }
+}
+
+impl DualContouring {
+    fn solve_qef(&self, cube: Vec3, crossings: &[Crossing]) -> Vec3 {
+        let mass_point = mass_point(crossings);
+        let mut a = Mat3::IDENTITY * self.bias;
+        let mut b = mass_point * self.bias;
+        for crossing in crossings.iter() {
+            let n = crossing.normal;
+            a += Mat3::from_cols(n * n.x, n * n.y, n * n.z);
+            b += n * n.dot(crossing.pos);
+        }
+
+        if a.determinant().abs() < 1e-6 {
+            return mass_point;
+        }
+        // the vertex must stay in its cube, or triangles would overlap their neighbours
+        (a.inverse() * b).clamp(cube, cube + Vec3::ONE)
+    }
+}
+
+impl Mesher for DualContouring {
+    fn mesh_region(
+        &self,
+        block: &mut MeshBlock,
+        field: &Vec<Voxel>,
+        extent: FieldExtent,
+        region: Region,
+    ) {
+        mesh_dual(block, field, extent, region, |cube, crossings| {
+            self.solve_qef(cube, crossings)
+        });
+    }
+}
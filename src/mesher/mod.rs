@@ -0,0 +1,291 @@
+use bevy::math::Vec3;
+
+use crate::common::coords::LocalVoxelCoords;
+use crate::common::field_extent::FieldExtent;
+use crate::common::region::Region;
+use crate::common::vertex::Vertex;
+use crate::common::voxels::Voxel;
+use crate::marching_cubes::{MarchingCubes, MarchingCubesVariant};
+
+pub mod ambient_occlusion;
+mod blocky;
+mod dual;
+mod dual_contouring;
+mod skirt;
+mod surface_nets;
+
+pub use blocky::{solid_boxes, Blocky};
+pub use dual_contouring::DualContouring;
+pub use skirt::push_skirts;
+pub use surface_nets::SurfaceNets;
+
+/// Value of MeshBlock::keys for vertices that cannot be shared with another block
+pub const UNSHARED_VERTEX: usize = usize::MAX;
+
+/// Triangles with an area below this are degenerate, they get merged into their neighbours (see
+/// collapse_slivers)
+pub(crate) const DEGENERATE_AREA: f32 = 1e-6;
+
+/// Vertices are kept at least this far (as a fraction of the edge) from the voxels, so that the
+/// vertices of the edges around a voxel with a value of 0 do not end up at the same position
+pub(crate) const MIN_EDGE_OFFSET: f32 = 0.01;
+
+/// Extracts the surface of a voxel field (where the value goes from negative to positive)
+/// Meshers work on a region of the field at a time, the vertices they generate are keyed so that
+/// the blocks of neighbouring regions can be welded back together
+pub trait Mesher {
+    /// Appends the triangles of the cubes of the field lying in region to block
+    fn mesh_region(
+        &self,
+        block: &mut MeshBlock,
+        field: &Vec<Voxel>,
+        extent: FieldExtent,
+        region: Region,
+    );
+}
+
+/// Vertices and triangles generated by a mesher for a region of a field
+#[derive(Clone, Default)]
+pub struct MeshBlock {
+    pub vertices: Vec<Vertex>,
+    // triangles, as triplets of indices into vertices
+    pub indices: Vec<u32>,
+    // key of each vertex in the whole field, smaller than 3 times the volume of the field (or
+    // UNSHARED_VERTEX), vertices of neighbouring blocks with the same key get welded
+    pub keys: Vec<usize>,
+    // triangles dropped because they had a repeated vertex
+    pub degenerate_triangles: usize,
+}
+
+impl MeshBlock {
+    /// Appends a triangle, unless it has a repeated vertex
+    /// Triangles of distinct vertices with (almost) no area are kept, their neighbours share
+    /// their edges, they are merged once the blocks are welded together (see collapse_slivers)
+    pub fn push_triangle(&mut self, a: u32, b: u32, c: u32) {
+        if a == b || b == c || c == a {
+            self.degenerate_triangles += 1;
+            return;
+        }
+        self.indices.push(a);
+        self.indices.push(b);
+        self.indices.push(c);
+    }
+}
+
+/// Merges the triangles with (almost) no area into their neighbours by collapsing their shortest
+/// edge, its second vertex being replaced by the first one in all the triangles, which removes
+/// the triangles on both sides of the edge and keeps the surface closed (they would have no
+/// normal, and break trimesh colliders)
+/// Returns the number of triangles removed
+pub fn collapse_slivers(vertices: &[Vertex], indices: &mut Vec<u32>) -> usize {
+    let position = |i: u32| Vec3::from(vertices[i as usize].pos.into_inners_arr());
+    // vertex replacing each vertex, following the collapsed edges
+    let mut remap: Vec<u32> = (0..vertices.len() as u32).collect();
+    let resolve = |remap: &Vec<u32>, mut i: u32| {
+        while remap[i as usize] != i {
+            i = remap[i as usize];
+        }
+        i
+    };
+
+    let mut collapsed = false;
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|k| resolve(&remap, triangle[k]));
+        if a == b || b == c || c == a {
+            continue;
+        }
+        let (pa, pb, pc) = (position(a), position(b), position(c));
+        if 0.5 * (pb - pa).cross(pc - pa).length() >= DEGENERATE_AREA {
+            continue;
+        }
+        let (kept, removed) = [(a, b), (b, c), (c, a)]
+            .into_iter()
+            .min_by(|&(a, b), &(c, d)| {
+                let (ab, cd) = (
+                    position(a).distance_squared(position(b)),
+                    position(c).distance_squared(position(d)),
+                );
+                ab.total_cmp(&cd)
+            })
+            .unwrap();
+        remap[removed as usize] = kept;
+        collapsed = true;
+    }
+    if !collapsed {
+        return 0;
+    }
+
+    let triangle_count = indices.len() / 3;
+    let remapped: Vec<u32> = indices
+        .chunks_exact(3)
+        .map(|triangle| [0, 1, 2].map(|k| resolve(&remap, triangle[k])))
+        .filter(|&[a, b, c]| a != b && b != c && c != a)
+        .flatten()
+        .collect();
+    *indices = remapped;
+    triangle_count - indices.len() / 3
+}
+
+/// Surface extraction algorithm used by an entity
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MesherKind {
+    MarchingCubes(MarchingCubesVariant),
+    /// One vertex per cube, at the average of the points where the surface crosses its edges,
+    /// giving fewer and better shaped triangles
+    SurfaceNets,
+    /// One vertex per cube, placed by minimizing the distance to the planes of the surface
+    /// where it crosses the edges, which keeps sharp features
+    DualContouring,
+    /// Each solid voxel is a cube, faces of a same material being merged into large quads
+    Blocky,
+}
+
+impl Default for MesherKind {
+    fn default() -> Self {
+        Self::MarchingCubes(MarchingCubesVariant::default())
+    }
+}
+
+impl MesherKind {
+    /// Number of cubes before and after a changed voxel (along each axis) whose part of the mesh
+    /// can change with it
+    /// A voxel is a corner of the cubes right before and after it, and its value is also used for
+    /// the normals of the vertices around its neighbours. Dual meshers place the vertex of a cube
+    /// from these normals, and the blocks of the cubes right after it generate it again to build
+    /// their quads, so one more cube after the voxel gets stale
+    pub fn dirty_margins(&self) -> (i32, i32) {
+        match self {
+            MesherKind::SurfaceNets | MesherKind::DualContouring => (2, 2),
+            MesherKind::MarchingCubes(_) | MesherKind::Blocky => (2, 1),
+        }
+    }
+}
+
+impl Mesher for MesherKind {
+    fn mesh_region(
+        &self,
+        block: &mut MeshBlock,
+        field: &Vec<Voxel>,
+        extent: FieldExtent,
+        region: Region,
+    ) {
+        match *self {
+            MesherKind::MarchingCubes(variant) => {
+                MarchingCubes { variant }.mesh_region(block, field, extent, region)
+            }
+            MesherKind::SurfaceNets => SurfaceNets.mesh_region(block, field, extent, region),
+            MesherKind::DualContouring => {
+                DualContouring::default().mesh_region(block, field, extent, region)
+            }
+            MesherKind::Blocky => Blocky.mesh_region(block, field, extent, region),
+        }
+    }
+}
+
+pub(crate) fn get_voxel(field: &Vec<Voxel>, pos: LocalVoxelCoords, extent: FieldExtent) -> Voxel {
+    field[extent.index(pos.x as usize, pos.y as usize, pos.z as usize)]
+}
+
+/// Density gradient at a voxel of the field, using central differences
+/// (one-sided differences on the borders of the field)
+pub(crate) fn density_gradient(
+    field: &Vec<Voxel>,
+    pos: LocalVoxelCoords,
+    extent: FieldExtent,
+) -> Vec3 {
+    let diff = |lo: LocalVoxelCoords, hi: LocalVoxelCoords, dist: f32| {
+        (get_voxel(field, hi, extent).value - get_voxel(field, lo, extent).value) / dist
+    };
+    let axis_diff = |p: u32, size: usize, along: fn(LocalVoxelCoords, u32) -> LocalVoxelCoords| {
+        let lo = p.saturating_sub(1);
+        let hi = (p + 1).min(size as u32 - 1);
+        diff(along(pos, lo), along(pos, hi), (hi - lo).max(1) as f32)
+    };
+
+    Vec3::new(
+        axis_diff(pos.x, extent.x, |c, x| LocalVoxelCoords::new(x, c.y, c.z)),
+        axis_diff(pos.y, extent.y, |c, y| LocalVoxelCoords::new(c.x, y, c.z)),
+        axis_diff(pos.z, extent.z, |c, z| LocalVoxelCoords::new(c.x, c.y, z)),
+    )
+}
+
+/// Trilinear interpolation of the density, the outside of the field being air
+pub(crate) fn sample_density(field: &Vec<Voxel>, extent: FieldExtent, pos: Vec3) -> f32 {
+    let base = pos.floor();
+    let t = pos - base;
+    let mut density = 0.;
+    for corner in 0..8 {
+        let offset = Vec3::new(
+            (corner & 1) as f32,
+            ((corner >> 1) & 1) as f32,
+            ((corner >> 2) & 1) as f32,
+        );
+        let weight = (if offset.x > 0. { t.x } else { 1. - t.x })
+            * (if offset.y > 0. { t.y } else { 1. - t.y })
+            * (if offset.z > 0. { t.z } else { 1. - t.z });
+        let c = base + offset;
+        let value = if extent.contains(c.x as i64, c.y as i64, c.z as i64) {
+            field[extent.index(c.x as usize, c.y as usize, c.z as usize)].value
+        } else {
+            -1.
+        };
+        density += weight * value;
+    }
+    density
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::color::Color;
+
+    use super::*;
+    use crate::common::coords::WorldCoords;
+    use crate::common::voxel_material::VoxelMaterial;
+    use crate::mesh_quality::MeshValidation;
+
+    fn vertex(x: f32, y: f32, z: f32) -> Vertex {
+        Vertex {
+            pos: WorldCoords::new(x, y, z),
+            normal: Vec3::Y,
+            color: Color::WHITE,
+            voxel_material: VoxelMaterial::STONE,
+            occlusion: 1.,
+        }
+    }
+
+    #[test]
+    fn slivers_are_merged_into_their_neighbours() {
+        // tetrahedron whose face 0 1 2 is split at vertex 4, lying on the edge 0 1, which leaves
+        // the sliver 0 1 4 between the two halves and the face 1 0 3
+        let vertices = vec![
+            vertex(0., 0., 0.),
+            vertex(1., 0., 0.),
+            vertex(0., 1., 0.),
+            vertex(0., 0., 1.),
+            vertex(0.3, 0., 0.),
+        ];
+        let mut block = MeshBlock {
+            vertices,
+            ..Default::default()
+        };
+        for [a, b, c] in [
+            [0, 4, 2],
+            [4, 1, 2],
+            [1, 0, 3],
+            [0, 2, 3],
+            [1, 3, 2],
+            [0, 1, 4],
+        ] {
+            block.push_triangle(a, b, c);
+        }
+        assert_eq!(block.degenerate_triangles, 0);
+        assert!(MeshValidation::validate(&block.vertices, &block.indices).is_closed_manifold());
+
+        let removed = collapse_slivers(&block.vertices, &mut block.indices);
+        let report = MeshValidation::validate(&block.vertices, &block.indices);
+        assert_eq!(removed, 2);
+        assert_eq!(report.triangle_count, 4);
+        assert_eq!(report.degenerate_triangles, 0);
+        assert!(report.is_closed_manifold());
+    }
+}
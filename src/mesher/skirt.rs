@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use bevy::math::Vec3;
+
+use crate::common::coords::WorldCoords;
+use crate::common::region::Region;
+use crate::common::vertex::Vertex;
+use crate::mesher::{MeshBlock, UNSHARED_VERTEX};
+
+// Blocks meshed at different levels of detail do not meet on their common face: the borders of
+// their meshes are lines of the face that are up to a cube of the coarser level apart, leaving
+// cracks between them. A skirt is a strip of triangles hanging from the border of a block mesh,
+// in the plane of the face, towards the inside of the matter. The skirts of both blocks cover
+// the part of the face between their borders, whichever side of the other each border is.
+
+/// Vertices closer than this (in voxels) are the same vertex when looking for the borders of a
+/// block mesh
+const POSITION_PRECISION: f32 = 1. / 1024.;
+
+/// Position of a vertex, in multiples of POSITION_PRECISION
+type VertexKey = [i32; 3];
+
+/// Appends to skirts the skirts of the borders of block lying on the faces of region
+/// (-x, +x, -y, +y, -z, +z) that have a depth, in voxels
+/// Borders belong to the closest of these faces if it is at most tolerance away from them (the
+/// vertices of dual meshers are up to a cube away from the faces of their region)
+/// Skirts are seen from both sides, since cracks can be seen from either side of a face
+pub fn push_skirts(
+    skirts: &mut MeshBlock,
+    block: &MeshBlock,
+    region: Region,
+    tolerance: f32,
+    depths: [Option<f32>; 6],
+) {
+    if depths.iter().all(Option::is_none) {
+        return;
+    }
+    let pos = |i: u32| Vec3::from(block.vertices[i as usize].pos.into_inners_arr());
+    let key = |i: u32| (pos(i) / POSITION_PRECISION).round().as_ivec3().to_array();
+
+    // triangles using each edge (by the positions of its ends), with the first of its directions
+    let mut edges: HashMap<(VertexKey, VertexKey), (u32, u32, usize)> = HashMap::new();
+    for t in block.indices.chunks_exact(3) {
+        for k in 0..3 {
+            let (a, b) = (t[k], t[(k + 1) % 3]);
+            let (ka, kb) = (key(a), key(b));
+            edges.entry((ka.min(kb), ka.max(kb))).or_insert((a, b, 0)).2 += 1;
+        }
+    }
+
+    let min = Vec3::new(
+        region.min.x as f32,
+        region.min.y as f32,
+        region.min.z as f32,
+    );
+    let max = Vec3::new(
+        region.max.x as f32,
+        region.max.y as f32,
+        region.max.z as f32,
+    );
+    // edges used by a single triangle are on the border of the mesh
+    let mut borders: Vec<_> = edges
+        .into_values()
+        .filter(|&(_, _, uses)| uses == 1)
+        .map(|(a, b, _)| (a, b))
+        .collect();
+    // in the order of their vertices, for a result that does not depend on the hash map
+    borders.sort_unstable();
+    for (a, b) in borders {
+        let middle = (pos(a) + pos(b)) / 2.;
+        // borders of the mesh only come from the faces that have skirts, the ones of the other
+        // faces being welded to the neighbouring blocks
+        let Some((face, depth)) = (0..6)
+            .filter_map(|face| {
+                let plane = if face % 2 == 0 { min } else { max };
+                let distance = (middle[face / 2] - plane[face / 2]).abs();
+                Some((face, depths[face]?, distance))
+            })
+            .filter(|&(_, _, distance)| distance <= tolerance)
+            .min_by(|x, y| x.2.total_cmp(&y.2))
+            .map(|(face, depth, _)| (face, depth))
+        else {
+            continue;
+        };
+
+        let mut face_normal = Vec3::ZERO;
+        face_normal[face / 2] = if face % 2 == 0 { -1. } else { 1. };
+        let first = skirts.vertices.len() as u32;
+        for (v, lowered) in [(a, false), (b, false), (b, true), (a, true)] {
+            let vertex = &block.vertices[v as usize];
+            let mut p = pos(v);
+            if lowered {
+                // into the matter, staying in the plane of the face
+                let normal = vertex.normal;
+                p -= (normal - face_normal * normal.dot(face_normal)).normalize_or_zero() * depth;
+            }
+            skirts.vertices.push(Vertex {
+                pos: WorldCoords::new(p.x, p.y, p.z),
+                ..vertex.clone()
+            });
+            skirts.keys.push(UNSHARED_VERTEX);
+        }
+        for [i, j, k] in [[0, 1, 2], [0, 2, 3], [0, 2, 1], [0, 3, 2]] {
+            skirts.push_triangle(first + i, first + j, first + k);
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use crate::common::field_extent::FieldExtent;
+use crate::common::region::Region;
+use crate::common::voxels::Voxel;
+use crate::mesher::dual::{mass_point, mesh_dual};
+use crate::mesher::{MeshBlock, Mesher};
+
+/// Naive surface nets: the vertex of each cube is the average of the points where the surface
+/// crosses its edges
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct SurfaceNets;
+
+impl Mesher for SurfaceNets {
+    fn mesh_region(
+        &self,
+        block: &mut MeshBlock,
+        field: &Vec<Voxel>,
+        extent: FieldExtent,
+        region: Region,
+    ) {
+        mesh_dual(block, field, extent, region, |_, crossings| {
+            mass_point(crossings)
+        });
+    }
+}
@@ -0,0 +1,182 @@
+use bevy::math::{Quat, Vec3, Vec4};
+
+// Everything here only uses integer arithmetic, floor and basic floating point operations
+// (no sin, exp or platform dependent random generator), so that a seed always gives the exact
+// same values.
+
+/// Seeded 3D gradient noise (Perlin's improved noise with a hash instead of a permutation
+/// table), and its fractal combinations
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Noise {
+    pub seed: u32,
+}
+
+/// Directions of the gradients: the middles of the 12 edges of a cube
+const GRADIENTS: [Vec3; 12] = [
+    Vec3::new(1., 1., 0.),
+    Vec3::new(-1., 1., 0.),
+    Vec3::new(1., -1., 0.),
+    Vec3::new(-1., -1., 0.),
+    Vec3::new(1., 0., 1.),
+    Vec3::new(-1., 0., 1.),
+    Vec3::new(1., 0., -1.),
+    Vec3::new(-1., 0., -1.),
+    Vec3::new(0., 1., 1.),
+    Vec3::new(0., -1., 1.),
+    Vec3::new(0., 1., -1.),
+    Vec3::new(0., -1., -1.),
+];
+
+fn hash(x: i32, y: i32, z: i32, seed: u32) -> u32 {
+    let mut h = seed ^ 0x9e37_79b9;
+    for v in [x, y, z] {
+        h ^= v as u32;
+        h = h.wrapping_mul(0x85eb_ca6b);
+        h ^= h >> 13;
+        h = h.wrapping_mul(0xc2b2_ae35);
+        h ^= h >> 16;
+    }
+    h
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+impl Noise {
+    pub fn new(seed: u32) -> Self {
+        Self { seed }
+    }
+
+    /// Noise for another octave (or coordinate) uncorrelated with this one
+    fn derived(&self, index: u32) -> Self {
+        Self::new(hash(index as i32, 0, 0, self.seed))
+    }
+
+    /// Gradient noise, roughly between -1 and 1, with features about 1 unit wide
+    pub fn gradient(&self, p: Vec3) -> f32 {
+        let cell = p.floor();
+        let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+        let f = p - cell;
+
+        let corner = |dx: i32, dy: i32, dz: i32| {
+            let g = GRADIENTS[(hash(x + dx, y + dy, z + dz, self.seed) % 12) as usize];
+            g.dot(f - Vec3::new(dx as f32, dy as f32, dz as f32))
+        };
+        let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+        lerp(
+            lerp(
+                lerp(corner(0, 0, 0), corner(1, 0, 0), u),
+                lerp(corner(0, 1, 0), corner(1, 1, 0), u),
+                v,
+            ),
+            lerp(
+                lerp(corner(0, 0, 1), corner(1, 0, 1), u),
+                lerp(corner(0, 1, 1), corner(1, 1, 1), u),
+                v,
+            ),
+            w,
+        )
+    }
+
+    /// Fractal Brownian motion: octaves of noise, each one with twice the frequency and half the
+    /// amplitude of the previous one, roughly between -1 and 1
+    pub fn fbm(&self, p: Vec3, octaves: u32) -> f32 {
+        let (mut sum, mut amplitude, mut total, mut frequency) = (0., 1., 0., 1.);
+        for octave in 0..octaves {
+            sum += amplitude * self.derived(octave).gradient(p * frequency);
+            total += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.;
+        }
+        sum / f32::max(total, f32::EPSILON)
+    }
+
+    /// Ridged noise: octaves of inverted absolute noise, giving sharp crests, between 0 and 1
+    pub fn ridged(&self, p: Vec3, octaves: u32) -> f32 {
+        let (mut sum, mut amplitude, mut total, mut frequency) = (0., 1., 0., 1.);
+        for octave in 0..octaves {
+            let ridge = 1. - self.derived(octave).gradient(p * frequency).abs().min(1.);
+            sum += amplitude * ridge * ridge;
+            total += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.;
+        }
+        sum / f32::max(total, f32::EPSILON)
+    }
+
+    /// Domain warping: moves p by up to about strength along each axis, following fBm
+    pub fn warp(&self, p: Vec3, strength: f32, octaves: u32) -> Vec3 {
+        p + strength
+            * Vec3::new(
+                self.derived(100).fbm(p, octaves),
+                self.derived(101).fbm(p, octaves),
+                self.derived(102).fbm(p, octaves),
+            )
+    }
+}
+
+/// Small seeded random number generator (SplitMix64), for the placement of features like
+/// craters
+#[derive(Clone, Debug)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform between 0 (included) and 1 (excluded)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// Uniformly distributed direction
+    pub fn direction(&mut self) -> Vec3 {
+        loop {
+            let v = Vec3::new(
+                self.range(-1., 1.),
+                self.range(-1., 1.),
+                self.range(-1., 1.),
+            );
+            let length_squared = v.length_squared();
+            if length_squared > 1e-4 && length_squared <= 1. {
+                return v / length_squared.sqrt();
+            }
+        }
+    }
+
+    /// Uniformly distributed rotation
+    pub fn rotation(&mut self) -> Quat {
+        loop {
+            let v = Vec4::new(
+                self.range(-1., 1.),
+                self.range(-1., 1.),
+                self.range(-1., 1.),
+                self.range(-1., 1.),
+            );
+            let length_squared = v.length_squared();
+            if length_squared > 1e-4 && length_squared <= 1. {
+                return Quat::from_vec4(v / length_squared.sqrt());
+            }
+        }
+    }
+}
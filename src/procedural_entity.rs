@@ -1,266 +1,882 @@
-use bevy::{ecs::component::Component, math::Vec3, utils::Instant};
-use std::arch::x86_64::*;
-use std::cmp::{max, min};
+use bevy::{
+    ecs::component::Component,
+    math::{IVec3, Quat, Vec3},
+    tasks::{ComputeTaskPool, TaskPool},
+    utils::Instant,
+};
 
 use crate::common::{
-    coords::{VoxelCoords, WorldCoords},
+    coords::{Coords, ICoords, LocalVoxelCoords, OutOfFieldError},
+    field_extent::FieldExtent,
+    region::Region,
     vertex::Vertex,
-    voxel_material::VoxelMaterial,
+    voxel_material::{MaterialRegistry, VoxelMaterial},
     voxels::Voxel,
 };
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+
+use crate::brush::Brush;
+use crate::clipboard::{BlendMode, VoxelClip};
+use crate::mesh_quality::{MeshStats, MeshValidation};
+use crate::mesh_simplify::{self, SimplifySettings};
+use crate::mesher::ambient_occlusion::{self, AmbientOcclusion};
+use crate::mesher::{self, sample_density, MeshBlock, Mesher, MesherKind, UNSHARED_VERTEX};
+use crate::sdf::SdfNode;
+use crate::symmetry::Symmetry;
+
+/// Number of cubes along each axis of a mesh block
+/// Only the blocks touched by an edit get remeshed
+const MESH_BLOCK_SIZE: u32 = 8;
+
+/// Voxel values are kept between -DENSITY_BAND and DENSITY_BAND, further voxels do not change
+/// the surface and would only make edits take longer to reach it
+pub const DENSITY_BAND: f32 = 1.0;
+
+/// Number of levels of detail of an entity mesh, level n is meshed from a field downsampled
+/// 2^n times (level 0 being the full resolution mesh)
+/// Blocks of the downsampled levels cover the same voxels as the full resolution ones, so 2^n
+/// cannot go past MESH_BLOCK_SIZE
+pub const LOD_LEVELS: usize = 4;
+
+/// Number of simplified rendered meshes kept, each for different levels of detail of the blocks
+const SIMPLIFIED_MESH_CACHE: usize = 4;
+
+/// How the normals of the rendered mesh are computed
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum NormalMode {
+    /// One normal per triangle, giving a faceted look
+    Flat,
+    /// Normals taken from the density gradient of the voxel field
+    #[default]
+    Smooth,
+}
+
+/// Materials of the voxels depending on their depth below the surface
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MaterialLayering {
+    pub surface: VoxelMaterial,
+    // thickness of the surface layer, in voxels
+    pub surface_depth: f32,
+    pub core: VoxelMaterial,
+}
+
+impl Default for MaterialLayering {
+    /// A layer of dirt covering a stone core
+    fn default() -> Self {
+        Self {
+            surface: VoxelMaterial::DIRT,
+            surface_depth: 3.0,
+            core: VoxelMaterial::STONE,
+        }
+    }
+}
+
+impl MaterialLayering {
+    /// Material of a voxel from its value (its depth below the surface)
+    pub fn material(&self, value: f32) -> VoxelMaterial {
+        if value >= self.surface_depth {
+            self.core
+        } else if value >= 0.0 {
+            self.surface
+        } else {
+            VoxelMaterial::AIR
+        }
+    }
+}
 
-use crate::marching_cubes;
+/// What an edit did to the voxels of an entity
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct EditReport {
+    // number of voxels whose value or material changed
+    pub changed: usize,
+    // matter removed from and added to each material, in voxels (a voxel going from fully solid
+    // to empty counts as 1)
+    pub removed: HashMap<VoxelMaterial, f32>,
+    pub added: HashMap<VoxelMaterial, f32>,
+}
+
+impl EditReport {
+    fn record(&mut self, material: VoxelMaterial, before: f32, after: f32) {
+        self.changed += 1;
+        // how full of matter a voxel is, from its value (none below the surface)
+        let matter = |value: f32| (value / DENSITY_BAND).clamp(0., 1.);
+        let amount = matter(after) - matter(before);
+        if amount < 0. {
+            *self.removed.entry(material).or_default() -= amount;
+        } else if amount > 0. {
+            *self.added.entry(material).or_default() += amount;
+        }
+    }
+
+    /// Adds the changes of another edit to this report
+    pub fn merge(&mut self, other: EditReport) {
+        self.changed += other.changed;
+        for (material, amount) in other.removed {
+            *self.removed.entry(material).or_default() += amount;
+        }
+        for (material, amount) in other.added {
+            *self.added.entry(material).or_default() += amount;
+        }
+    }
+
+    /// Total mass removed by the edit, from the densities of the materials
+    pub fn removed_mass(&self, materials: &MaterialRegistry) -> f32 {
+        self.removed
+            .iter()
+            .map(|(material, amount)| amount * materials.properties(*material).density)
+            .sum()
+    }
+}
+
+/// Downsampled field of a level of detail, with the meshes of its blocks (generated when first
+/// needed)
+#[derive(Clone)]
+struct LodLevel {
+    field: Vec<Voxel>,
+    extent: FieldExtent,
+    blocks: Vec<Option<MeshBlock>>,
+}
+
+/// Simplified mesh, kept until the mesh gets regenerated
+#[derive(Clone)]
+struct SimplifiedMesh {
+    settings: SimplifySettings,
+    // levels of detail of the blocks it was simplified from (see ProceduralEntity::block_levels)
+    levels: Vec<usize>,
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+}
+
+/// Entities left after an edit (several when it split the entity), and what the edit did
+pub type EditResult = Result<(Vec<ProceduralEntity>, EditReport), OutOfFieldError>;
 
 #[derive(Component)]
 pub struct ProceduralEntity {
-    pub field_size: usize,
+    // number of voxels along each axis of the field
+    pub extent: FieldExtent,
 
     // voxel field describing the geometry of the Entity
     // actual vertices will be determined by marching squares
     pub voxel_field: Vec<Voxel>,
 
     pub vertices: Vec<Vertex>,
+    // triangles of the mesh, as triplets of indices into vertices
+    pub indices: Vec<u32>,
+
+    pub normal_mode: NormalMode,
+    // number of texture repeats per voxel of the triplanar UVs
+    pub texture_scale: f32,
+    pub mesher: MesherKind,
+    // optional ambient occlusion baked into the vertices, off by default since it samples the
+    // field in many directions around each vertex, every time a block is remeshed
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+    // optional simplification of the rendered mesh and of the collider
+    pub render_simplification: Option<SimplifySettings>,
+    pub collider_simplification: Option<SimplifySettings>,
+    // replication of the edits made by entity_deform_system
+    pub symmetry: Symmetry,
+
+    // marching cubes output cached per block of the field, spliced together into vertices
+    mesh_blocks: Vec<MeshBlock>,
+    // blocks whose voxels changed since they were last meshed
+    dirty_blocks: Vec<bool>,
+    // mesher settings the mesh blocks were generated with
+    mesh_blocks_settings: Option<(MesherKind, Option<AmbientOcclusion>)>,
+    // downsampled levels of detail by level (level 0 being mesh_blocks), generated when first
+    // needed
+    lod_levels: Vec<Option<LodLevel>>,
+    // rendered meshes simplified with render_simplification (most recently used last), and the
+    // collider mesh simplified with collider_simplification
+    simplified_meshes: Vec<SimplifiedMesh>,
+    simplified_collider: Option<SimplifiedMesh>,
+
+    pub stats: MeshStats,
 
     pub modification_count: usize,
     pub modification_threshold: usize,
 }
 
 impl ProceduralEntity {
-    pub fn new(field_size: usize) -> Self {
+    pub fn new(extent: FieldExtent) -> Self {
         Self {
             // pos,
-            field_size, // Initialize field size
+            extent, // Initialize field size
             voxel_field: Vec::new(),
             vertices: Vec::new(),
+            indices: Vec::new(),
+            normal_mode: NormalMode::default(),
+            texture_scale: 0.25,
+            mesher: MesherKind::default(),
+            ambient_occlusion: None,
+            render_simplification: None,
+            collider_simplification: None,
+            symmetry: Symmetry::default(),
+            mesh_blocks: Vec::new(),
+            dirty_blocks: Vec::new(),
+            mesh_blocks_settings: None,
+            lod_levels: Vec::new(),
+            simplified_meshes: Vec::new(),
+            simplified_collider: None,
+            stats: MeshStats::default(),
             modification_count: 0,
             modification_threshold: 20, // Adjust based on your needs}
         }
     }
 
+    /// Entity whose voxels sample the given signed distance function (in voxels of the field)
+    pub fn from_sdf(tree: &SdfNode, extent: FieldExtent) -> Self {
+        let mut entity = Self::new(extent);
+        entity.fill_from_sdf(tree);
+        entity
+    }
+
+    /// Default shape: the intersection of two spheres
     pub fn generate_voxels(&mut self) {
-        let start = Instant::now();
-        self.voxel_field = vec![
-            Voxel {
-                value: -1.0,
-                material: VoxelMaterial::AIR
-            };
-            self.field_size * self.field_size * self.field_size
-        ];
+        let center = Vec3::new(
+            (self.extent.x / 2) as f32,
+            (self.extent.y / 2) as f32,
+            (self.extent.z / 2) as f32,
+        );
+        let tree = SdfNode::sphere(center - Vec3::splat(3.), 15.)
+            .intersection(SdfNode::sphere(center + Vec3::splat(3.), 15.));
+        self.fill_from_sdf(&tree);
+    }
 
-        // sphere of radius 4 (centered at 10 10 10)
-        for (i, v) in self.voxel_field.iter_mut().enumerate() {
-            let center1 = VoxelCoords::new(
-                self.field_size as u8 / 2 - 3,
-                self.field_size as u8 / 2 - 3,
-                self.field_size as u8 / 2 - 3,
-            );
-            let center2 = VoxelCoords::new(
-                self.field_size as u8 / 2 + 3,
-                self.field_size as u8 / 2 + 3,
-                self.field_size as u8 / 2 + 3,
-            );
-            let curr = VoxelCoords {
-                x: (i / (self.field_size * self.field_size)) as u8,
-                y: ((i % (self.field_size * self.field_size)) / self.field_size) as u8,
-                z: (i % self.field_size) as u8,
-            };
-            let dist = std::cmp::max(
-                ordered_float::OrderedFloat(Self::sdf_sphere(curr, center1, 15.0)),
-                ordered_float::OrderedFloat(Self::sdf_sphere(curr, center2, 15.0)),
-            );
-            v.value = -dist.into_inner();
-        }
-        let duration = start.elapsed();
-        println!("entity voxel duration: {:?}", duration);
+    /// Replaces the voxels by samples of a signed distance function
+    pub fn fill_from_sdf(&mut self, tree: &SdfNode) {
+        self.fill_from_distance(|pos| tree.distance(pos), MaterialLayering::default());
     }
 
-    pub fn sdf_sphere(pos: VoxelCoords, center: VoxelCoords, radius: f32) -> f32 {
-        let diff = center - pos;
-        let dist = ((diff.x * diff.x + diff.y * diff.y + diff.z * diff.z) as f64).sqrt();
-        dist as f32 - radius
+    /// Replaces the voxels by samples of a signed distance function (negative inside, in voxels
+    /// of the field), their material depending on their depth below the surface
+    /// Values are clamped to the density band
+    pub fn fill_from_distance(
+        &mut self,
+        distance: impl Fn(Vec3) -> f32,
+        layering: MaterialLayering,
+    ) {
+        let start = Instant::now();
+        let extent = self.extent;
+        self.voxel_field = (0..extent.volume())
+            .map(|i| {
+                let c = extent.coords(i);
+                let value = -distance(Vec3::new(c.x as f32, c.y as f32, c.z as f32));
+                Voxel {
+                    value: value.clamp(-DENSITY_BAND, DENSITY_BAND),
+                    material: layering.material(value),
+                }
+            })
+            .collect();
+        self.invalidate_mesh_blocks();
+        self.stats.voxel_generation_time = start.elapsed();
     }
 
+    /// Regenerates the vertices of the entity, only remeshing the blocks marked as dirty
+    /// Blocks are meshed in parallel on the compute task pool, then spliced in block order so the
+    /// result does not depend on the number of threads
     pub fn generate_vertices(&mut self) {
-        println!("field size: {}", self.field_size);
+        self.generate_vertices_on(ComputeTaskPool::get_or_init(TaskPool::default));
+    }
+
+    /// Regenerates the vertices of the entity like generate_vertices, meshing the blocks on the
+    /// given task pool
+    fn generate_vertices_on(&mut self, pool: &TaskPool) {
         let start = Instant::now();
-        self.vertices.clear();
-        marching_cubes::find_triangles(
-            &mut self.vertices,
-            &self.voxel_field,
-            self.field_size as u32,
-        );
-        let duration = start.elapsed();
-        println!("marching cubes: {:?}", duration);
+
+        let settings = (self.mesher, self.ambient_occlusion);
+        if self.mesh_blocks_settings != Some(settings) {
+            self.invalidate_mesh_blocks();
+            self.mesh_blocks_settings = Some(settings);
+        }
+
+        let blocks = self.block_counts();
+        let block_count = (blocks.x * blocks.y * blocks.z) as usize;
+        if self.mesh_blocks.len() != block_count {
+            self.mesh_blocks = vec![MeshBlock::default(); block_count];
+            self.dirty_blocks = vec![true; block_count];
+        }
+
+        let dirty: Vec<(usize, Region)> = (0..block_count)
+            .filter(|&i| self.dirty_blocks[i])
+            .map(|i| (i, self.block_region(i)))
+            .collect();
+
+        let field = &self.voxel_field;
+        let extent = self.extent;
+        let mesher = self.mesher;
+        let ambient_occlusion = self.ambient_occlusion;
+        // scope returns the output of the tasks in the order they were spawned
+        let meshed = pool.scope(|scope| {
+            for &(_, region) in dirty.iter() {
+                scope.spawn(async move {
+                    let mut block = MeshBlock::default();
+                    mesher.mesh_region(&mut block, field, extent, region);
+                    if let Some(settings) = ambient_occlusion {
+                        ambient_occlusion::bake_occlusion(&mut block, field, extent, settings);
+                    }
+                    block
+                });
+            }
+        });
+
+        for (&(i, _), block) in dirty.iter().zip(meshed) {
+            self.mesh_blocks[i] = block;
+            self.dirty_blocks[i] = false;
+        }
+        let collapsed = self.splice_mesh_blocks();
+        if !dirty.is_empty() {
+            self.lod_levels = vec![None; LOD_LEVELS];
+            self.simplified_meshes.clear();
+            self.simplified_collider = None;
+        }
+
+        self.stats.measure(&self.vertices, &self.indices);
+        self.stats.degenerate_triangles = self
+            .mesh_blocks
+            .iter()
+            .map(|block| block.degenerate_triangles)
+            .sum::<usize>()
+            + collapsed;
+        self.stats.remeshed_blocks = dirty.len();
+        self.stats.meshing_time = start.elapsed();
+    }
+
+    /// Centres of the mesh blocks, in voxels of the field, in the order of the levels of detail
+    /// given to lod_mesh
+    pub fn block_centers(&self) -> Vec<Vec3> {
+        let blocks = self.block_counts();
+        (0..(blocks.x * blocks.y * blocks.z) as usize)
+            .map(|i| {
+                let region = self.block_region(i);
+                let (min, max) = (region.min, region.max);
+                Vec3::new(
+                    (min.x + max.x) as f32,
+                    (min.y + max.y) as f32,
+                    (min.z + max.z) as f32,
+                ) / 2.
+            })
+            .collect()
     }
 
-    pub fn minimize_field_size(&mut self) {
+    /// Vertices and triangles of the mesh with each block at its own level of detail (given in
+    /// block order, missing levels being 0, the full resolution)
+    /// Downsampled blocks are cached until the next call to generate_vertices. The borders of
+    /// blocks of different levels do not match, skirts hanging from them into the matter cover
+    /// the cracks between them (see mesher::push_skirts)
+    pub fn lod_mesh(&mut self, levels: &[usize]) -> (Vec<Vertex>, Vec<u32>) {
+        let levels = self.block_levels(levels);
+        if levels.is_empty() {
+            return (self.vertices.clone(), self.indices.clone());
+        }
+
         let start = Instant::now();
-        // Find the bounding box of positive voxels
-        let mut min = VoxelCoords::new(
-            self.field_size as u8,
-            self.field_size as u8,
-            self.field_size as u8,
-        );
-        let mut max = VoxelCoords::new(0, 0, 0);
-
-        // Use SIMD to process chunks of voxels at once
-        let chunk_size = 8; // Assuming we're using AVX (256-bit registers)
-        let mut i = 0;
-        while i < self.voxel_field.len() {
-            let end = std::cmp::min(i + chunk_size, self.voxel_field.len());
-            let mut mask = 0u8;
-
-            unsafe {
-                let mut values = _mm256_setzero_ps();
-                for j in i..end {
-                    let value = _mm256_set1_ps(self.voxel_field[j].value);
-                    values = _mm256_max_ps(values, value);
+        for (i, &level) in levels.iter().enumerate() {
+            if level > 0 {
+                self.generate_lod_block(level, i);
+            }
+        }
+        let block_mesh = |i: usize| match levels[i] {
+            0 => &self.mesh_blocks[i],
+            level => self.lod_levels[level].as_ref().unwrap().blocks[i]
+                .as_ref()
+                .unwrap(),
+        };
+
+        // neighbours of a block across each of its faces (-x, +x, -y, +y, -z, +z)
+        let blocks = self.block_counts();
+        let (blocks_y, blocks_z) = (blocks.y as usize, blocks.z as usize);
+        let neighbours = |i: usize| {
+            let c = [
+                i / (blocks_y * blocks_z),
+                (i / blocks_z) % blocks_y,
+                i % blocks_z,
+            ];
+            let counts = [blocks.x as usize, blocks_y, blocks_z];
+            let strides = [blocks_y * blocks_z, blocks_z, 1];
+            std::array::from_fn::<_, 6, _>(|face| {
+                let axis = face / 2;
+                match face % 2 {
+                    0 if c[axis] > 0 => Some(i - strides[axis]),
+                    1 if c[axis] + 1 < counts[axis] => Some(i + strides[axis]),
+                    _ => None,
                 }
-                mask = _mm256_movemask_ps(values) as u8;
+            })
+        };
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        // vertices shared between blocks of the same level, by level and key
+        let mut welded: HashMap<(usize, usize), u32> = HashMap::new();
+        let mut skirts = MeshBlock::default();
+        for (i, &level) in levels.iter().enumerate() {
+            let block = block_mesh(i);
+            let remap: Vec<u32> = block
+                .vertices
+                .iter()
+                .zip(block.keys.iter())
+                .map(|(v, &key)| {
+                    if key != UNSHARED_VERTEX {
+                        if let Some(&index) = welded.get(&(level, key)) {
+                            return index;
+                        }
+                        welded.insert((level, key), vertices.len() as u32);
+                    }
+                    vertices.push(v.clone());
+                    vertices.len() as u32 - 1
+                })
+                .collect();
+            indices.extend(block.indices.iter().map(|&i| remap[i as usize]));
+
+            // skirts as deep as a cube of the coarser level on the faces between levels
+            let depths = neighbours(i).map(|neighbour| {
+                let other = levels[neighbour?];
+                (other != level).then(|| (1 << level.max(other)) as f32)
+            });
+            let step = (1 << level) as f32;
+            mesher::push_skirts(&mut skirts, block, self.block_region(i), step, depths);
+        }
+        let first = vertices.len() as u32;
+        vertices.extend(skirts.vertices);
+        indices.extend(skirts.indices.iter().map(|&i| first + i));
+        mesher::collapse_slivers(&vertices, &mut indices);
+
+        self.stats.lod_meshing_time = start.elapsed();
+        (vertices, indices)
+    }
+
+    /// Levels of detail of every block from the ones given to lod_mesh, empty when they are all
+    /// at full resolution
+    fn block_levels(&self, levels: &[usize]) -> Vec<usize> {
+        let levels: Vec<usize> = (0..self.mesh_blocks.len())
+            .map(|i| levels.get(i).map_or(0, |&level| level.min(LOD_LEVELS - 1)))
+            .collect();
+        if levels.iter().all(|&level| level == 0) {
+            return Vec::new();
+        }
+        levels
+    }
+
+    /// Mesh to render with the given levels of detail of the blocks (see lod_mesh), simplified if
+    /// render_simplification is set
+    /// Simplified meshes are cached until the next call to generate_vertices that changes the
+    /// mesh
+    pub fn render_mesh(&mut self, levels: &[usize]) -> (Vec<Vertex>, Vec<u32>) {
+        let Some(settings) = self.render_simplification else {
+            return self.lod_mesh(levels);
+        };
+        let levels = self.block_levels(levels);
+        if let Some(i) = self
+            .simplified_meshes
+            .iter()
+            .position(|mesh| mesh.settings == settings && mesh.levels == levels)
+        {
+            let mesh = self.simplified_meshes.remove(i);
+            let result = (mesh.vertices.clone(), mesh.indices.clone());
+            self.simplified_meshes.push(mesh);
+            return result;
+        }
+
+        let start = Instant::now();
+        let (vertices, indices) = self.lod_mesh(&levels);
+        let (vertices, indices) = mesh_simplify::simplify(&vertices, &indices, settings);
+        self.stats.simplification_time = start.elapsed();
+        if self.simplified_meshes.len() >= SIMPLIFIED_MESH_CACHE {
+            self.simplified_meshes.remove(0);
+        }
+        self.simplified_meshes.push(SimplifiedMesh {
+            settings,
+            levels,
+            vertices: vertices.clone(),
+            indices: indices.clone(),
+        });
+        (vertices, indices)
+    }
+
+    /// Mesh the collider is built from, always at full resolution, simplified (and cached like
+    /// the rendered mesh) if collider_simplification is set
+    pub fn collider_mesh(&mut self) -> (Vec<Vertex>, Vec<u32>) {
+        let Some(settings) = self.collider_simplification else {
+            return (self.vertices.clone(), self.indices.clone());
+        };
+        if let Some(mesh) = &self.simplified_collider {
+            if mesh.settings == settings {
+                return (mesh.vertices.clone(), mesh.indices.clone());
             }
+        }
 
-            for j in i..end {
-                if (mask & (1 << (j - i))) != 0 {
-                    let coords = self.index_to_coords(j);
-                    min.x = min.x.min(coords.x);
-                    min.y = min.y.min(coords.y);
-                    min.z = min.z.min(coords.z);
-                    max.x = max.x.max(coords.x);
-                    max.y = max.y.max(coords.y);
-                    max.z = max.z.max(coords.z);
+        let start = Instant::now();
+        let (vertices, indices) = mesh_simplify::simplify(&self.vertices, &self.indices, settings);
+        self.stats.simplification_time = start.elapsed();
+        self.simplified_collider = Some(SimplifiedMesh {
+            settings,
+            levels: Vec::new(),
+            vertices: vertices.clone(),
+            indices: indices.clone(),
+        });
+        (vertices, indices)
+    }
+
+    /// Boxes covering the solid voxels, as min and max corners, which a blocky entity builds its
+    /// collider from instead of its mesh
+    pub fn collider_boxes(&self) -> Vec<(Vec3, Vec3)> {
+        mesher::solid_boxes(&self.voxel_field, self.extent)
+    }
+
+    /// Meshes a block at a downsampled level of detail, unless it already was
+    fn generate_lod_block(&mut self, level: usize, index: usize) {
+        let step = 1 << level;
+        if self.lod_levels.len() != LOD_LEVELS {
+            self.lod_levels = vec![None; LOD_LEVELS];
+        }
+        if self.lod_levels[level].is_none() {
+            let (field, extent) = self.downsampled_field(step);
+            self.lod_levels[level] = Some(LodLevel {
+                field,
+                extent,
+                blocks: vec![None; self.mesh_blocks.len()],
+            });
+        }
+
+        // the same voxels as the full resolution block, block borders being multiples of step
+        let full_region = self.block_region(index);
+        let region = Region::new(
+            LocalVoxelCoords::new(
+                full_region.min.x / step as u32,
+                full_region.min.y / step as u32,
+                full_region.min.z / step as u32,
+            ),
+            LocalVoxelCoords::new(
+                full_region.max.x.div_ceil(step as u32),
+                full_region.max.y.div_ceil(step as u32),
+                full_region.max.z.div_ceil(step as u32),
+            ),
+        );
+        let (mesher, ambient_occlusion) = (self.mesher, self.ambient_occlusion);
+        let lod = self.lod_levels[level].as_mut().unwrap();
+        if lod.blocks[index].is_some() {
+            return;
+        }
+
+        let mut block = MeshBlock::default();
+        mesher.mesh_region(&mut block, &lod.field, lod.extent, region);
+        if let Some(settings) = ambient_occlusion {
+            // the occlusion radius is given in full resolution voxels
+            let settings = AmbientOcclusion {
+                radius: settings.radius / step as f32,
+                ..settings
+            };
+            ambient_occlusion::bake_occlusion(&mut block, &lod.field, lod.extent, settings);
+        }
+
+        // back to the coordinates of the full resolution field
+        for vertex in block.vertices.iter_mut() {
+            vertex.pos = vertex.pos * step as f32;
+        }
+        lod.blocks[index] = Some(block);
+    }
+
+    /// Downsamples the field by step along each axis, each voxel standing for the voxels up to
+    /// step / 2 away from it: its value is the average of theirs, but it stays solid if one of
+    /// them is (so that features thinner than step do not disappear), and it gets the material
+    /// of the most solid of them
+    /// Voxels outside of the field are air, and so are the voxels on the border of the
+    /// downsampled field, which keeps its surface closed
+    fn downsampled_field(&self, step: usize) -> (Vec<Voxel>, FieldExtent) {
+        let extent = FieldExtent::new(
+            (self.extent.x - 1).div_ceil(step) + 1,
+            (self.extent.y - 1).div_ceil(step) + 1,
+            (self.extent.z - 1).div_ceil(step) + 1,
+        );
+        let half = (step / 2) as i64;
+        let field = (0..extent.volume())
+            .map(|i| {
+                let c = extent.coords(i);
+                if c.x == 0
+                    || c.y == 0
+                    || c.z == 0
+                    || c.x == extent.x - 1
+                    || c.y == extent.y - 1
+                    || c.z == extent.z - 1
+                {
+                    return Voxel::from(-1.);
+                }
+                let (cx, cy, cz) = (
+                    (c.x * step) as i64,
+                    (c.y * step) as i64,
+                    (c.z * step) as i64,
+                );
+                let (mut sum, mut count) = (0., 0);
+                let mut most_solid = Voxel::from(-1.);
+                for x in cx - half..=cx + half {
+                    for y in cy - half..=cy + half {
+                        for z in cz - half..=cz + half {
+                            let voxel = if self.extent.contains(x, y, z) {
+                                self.voxel_field
+                                    [self.extent.index(x as usize, y as usize, z as usize)]
+                            } else {
+                                Voxel::from(-1.)
+                            };
+                            sum += voxel.value;
+                            count += 1;
+                            if voxel.value > most_solid.value {
+                                most_solid = voxel;
+                            }
+                        }
+                    }
+                }
+                let average = sum / count as f32;
+                Voxel {
+                    value: if most_solid.value >= 0. {
+                        average.max(0.)
+                    } else {
+                        average
+                    },
+                    material: most_solid.material,
                 }
+            })
+            .collect();
+        (field, extent)
+    }
+
+    /// Reports the boundary edges, non-manifold edges and degenerate triangles of the mesh
+    pub fn validate_mesh(&self) -> MeshValidation {
+        MeshValidation::validate(&self.vertices, &self.indices)
+    }
+
+    /// Marks the blocks affected by a change of the voxels between min and max (included)
+    /// so that they get remeshed by the next call to generate_vertices
+    pub fn mark_dirty(&mut self, min: ICoords, max: ICoords) {
+        if self.dirty_blocks.is_empty() {
+            // no block was meshed yet, everything will be generated anyway
+            return;
+        }
+
+        // the cubes around a voxel depending on it for the mesher (see MesherKind::dirty_margins),
+        // and the vertices within the occlusion radius for their occlusion
+        let (before, after) = self.mesher.dirty_margins();
+        let margin = self
+            .ambient_occlusion
+            .map_or(0, |ao| ao.radius.ceil() as i32 + 1);
+        let to_cube = |v: i32, size: usize| v.clamp(0, (size as i32 - 2).max(0)) as u32;
+        let dirty = Region::new(
+            Coords {
+                x: to_cube(min.x - before - margin, self.extent.x),
+                y: to_cube(min.y - before - margin, self.extent.y),
+                z: to_cube(min.z - before - margin, self.extent.z),
+            },
+            Coords {
+                x: to_cube(max.x + after + margin, self.extent.x) + 1,
+                y: to_cube(max.y + after + margin, self.extent.y) + 1,
+                z: to_cube(max.z + after + margin, self.extent.z) + 1,
+            },
+        );
+
+        for i in 0..self.dirty_blocks.len() {
+            if self.block_region(i).intersects(&dirty) {
+                self.dirty_blocks[i] = true;
             }
-            i += chunk_size;
         }
-        println!("MIN: {:?}", min);
-        println!("MAX: {:?}", max);
-        println!("field size (before change): {:?}", self.field_size);
+    }
+
+    /// Forces the whole mesh to be regenerated, needed when the layout of the field changes
+    fn invalidate_mesh_blocks(&mut self) {
+        self.mesh_blocks.clear();
+        self.dirty_blocks.clear();
+    }
+
+    /// Number of mesh blocks along each axis
+    fn block_counts(&self) -> Coords<u32> {
+        let count = |size: usize| (size as u32).saturating_sub(1).div_ceil(MESH_BLOCK_SIZE);
+        Coords {
+            x: count(self.extent.x),
+            y: count(self.extent.y),
+            z: count(self.extent.z),
+        }
+    }
+
+    /// Region of the field covered by a mesh block
+    fn block_region(&self, index: usize) -> Region {
+        let blocks = self.block_counts();
+        let (blocks_y, blocks_z) = (blocks.y as usize, blocks.z as usize);
+        let min = Coords {
+            x: (index / (blocks_y * blocks_z)) as u32 * MESH_BLOCK_SIZE,
+            y: ((index / blocks_z) % blocks_y) as u32 * MESH_BLOCK_SIZE,
+            z: (index % blocks_z) as u32 * MESH_BLOCK_SIZE,
+        };
+        let max = Coords {
+            x: (min.x + MESH_BLOCK_SIZE).min(self.extent.x as u32 - 1),
+            y: (min.y + MESH_BLOCK_SIZE).min(self.extent.y as u32 - 1),
+            z: (min.z + MESH_BLOCK_SIZE).min(self.extent.z as u32 - 1),
+        };
+        Region::new(min, max)
+    }
+
+    /// Concatenates the mesh blocks into the vertices and indices of the entity,
+    /// welding the vertices lying on the borders between blocks
+    /// Returns the number of triangles with no area merged into their neighbours
+    fn splice_mesh_blocks(&mut self) -> usize {
+        self.vertices.clear();
+        self.indices.clear();
+
+        let mut welded = vec![u32::MAX; 3 * self.voxel_field.len()];
+        for block in self.mesh_blocks.iter() {
+            let remap: Vec<u32> = block
+                .vertices
+                .iter()
+                .zip(block.keys.iter())
+                .map(|(v, &key)| {
+                    if key == UNSHARED_VERTEX {
+                        self.vertices.push(v.clone());
+                        return self.vertices.len() as u32 - 1;
+                    }
+                    if welded[key] == u32::MAX {
+                        welded[key] = self.vertices.len() as u32;
+                        self.vertices.push(v.clone());
+                    }
+                    welded[key]
+                })
+                .collect();
+            self.indices
+                .extend(block.indices.iter().map(|&i| remap[i as usize]));
+        }
+        mesher::collapse_slivers(&self.vertices, &mut self.indices)
+    }
+
+    /// Crops the field to the bounding box of its solid voxels, independently along each axis
+    /// Returns the offset of the new field in the old one (voxel c of the new field being voxel
+    /// c + offset of the old one)
+    pub fn minimize_field_size(&mut self) -> Coords<usize> {
+        let start = Instant::now();
+        let mut offset = Coords::default();
+        if let Some((min, new_extent)) = self.minimal_field() {
+            self.crop_field(min, new_extent);
+            offset = min;
+        }
+
+        self.stats.resize_time = start.elapsed();
+        offset
+    }
+
+    /// Offset in the field and extent of the bounding box of its solid voxels, padded with a
+    /// voxel on each side, None if the field has no solid voxel or is already that small
+    pub fn minimal_field(&self) -> Option<(Coords<usize>, FieldExtent)> {
+        // Find the bounding box of positive voxels
+        let (mut min, mut max) = self.solid_bounds()?;
 
         // Ensure min and max are within bounds
         min.x = min.x.max(1);
         min.y = min.y.max(1);
         min.z = min.z.max(1);
-        max.x = max.x.min(self.field_size as u8 - 2);
-        max.y = max.y.min(self.field_size as u8 - 2);
-        max.z = max.z.min(self.field_size as u8 - 2);
+        max.x = max.x.min(self.extent.x - 2);
+        max.y = max.y.min(self.extent.y - 2);
+        max.z = max.z.min(self.extent.z - 2);
 
-        // keep the largest
-        let new_size = ((max.x - min.x).max(max.y - min.y).max(max.z - min.z) + 3) as usize; // +3 for padding on both sides
-        if new_size < self.field_size {
-            self.rebuild_field(min, new_size);
+        // +3 for padding on both sides
+        let new_extent = FieldExtent::new(
+            max.x.saturating_sub(min.x) + 3,
+            max.y.saturating_sub(min.y) + 3,
+            max.z.saturating_sub(min.z) + 3,
+        );
+        if new_extent == self.extent {
+            return None;
         }
+        let offset = Coords {
+            x: min.x - 1,
+            y: min.y - 1,
+            z: min.z - 1,
+        };
+        Some((offset, new_extent))
+    }
 
-        let duration = start.elapsed();
-        println!("minimize_field_size duration: {:?}", duration);
+    /// Crops the field to the `extent` voxels starting at voxel `offset`, which becomes voxel
+    /// (0, 0, 0) (the voxels past the end of the current field are empty)
+    pub fn crop_field(&mut self, offset: Coords<usize>, extent: FieldExtent) {
+        let min = Coords {
+            x: offset.x + 1,
+            y: offset.y + 1,
+            z: offset.z + 1,
+        };
+        self.rebuild_field(min, extent);
+        self.symmetry.center -= Vec3::new(offset.x as f32, offset.y as f32, offset.z as f32);
     }
 
-    fn index_to_coords(&self, index: usize) -> VoxelCoords {
-        let z = (index % self.field_size) as u8;
-        let y = ((index / self.field_size) % self.field_size) as u8;
-        let x = (index / (self.field_size * self.field_size)) as u8;
-        VoxelCoords::new(x, y, z)
+    /// Replaces the whole field, all the mesh blocks getting remeshed
+    pub fn replace_field(&mut self, extent: FieldExtent, voxel_field: Vec<Voxel>) {
+        self.extent = extent;
+        self.voxel_field = voxel_field;
+        self.invalidate_mesh_blocks();
     }
 
-    fn rebuild_field(&mut self, min: VoxelCoords, new_size: usize) {
+    /// Bounding box (included) of the voxels with a positive value, None if there is none
+    fn solid_bounds(&self) -> Option<(Coords<usize>, Coords<usize>)> {
+        let mut bounds: Option<(Coords<usize>, Coords<usize>)> = None;
+        for (i, voxel) in self.voxel_field.iter().enumerate() {
+            if voxel.value < 0.0 {
+                continue;
+            }
+            let c = self.extent.coords(i);
+            bounds = Some(match bounds {
+                None => (c, c),
+                Some((min, max)) => (
+                    Coords {
+                        x: min.x.min(c.x),
+                        y: min.y.min(c.y),
+                        z: min.z.min(c.z),
+                    },
+                    Coords {
+                        x: max.x.max(c.x),
+                        y: max.y.max(c.y),
+                        z: max.z.max(c.z),
+                    },
+                ),
+            });
+        }
+        bounds
+    }
+
+    /// Replaces the field by a field of `new_extent` voxels,
+    /// old voxel min becomes voxel (1, 1, 1) of the new field
+    fn rebuild_field(&mut self, min: Coords<usize>, new_extent: FieldExtent) {
         let mut new_voxels = vec![
             Voxel {
                 value: -1.0,
                 material: VoxelMaterial::AIR,
             };
-            new_size * new_size * new_size
+            new_extent.volume()
         ];
 
-        for x in 0..new_size {
-            for y in 0..new_size {
-                for z in 0..new_size {
-                    let old_x = min.x as usize + x - 1;
-                    let old_y = min.y as usize + y - 1;
-                    let old_z = min.z as usize + z - 1;
-                    if old_x < self.field_size && old_y < self.field_size && old_z < self.field_size
-                    {
-                        let old_index = old_z
-                            + old_y * self.field_size
-                            + old_x * self.field_size * self.field_size;
-                        new_voxels[z + y * new_size + x * new_size * new_size] =
-                            self.voxel_field[old_index];
+        for x in 0..new_extent.x {
+            for y in 0..new_extent.y {
+                for z in 0..new_extent.z {
+                    let old_x = min.x + x - 1;
+                    let old_y = min.y + y - 1;
+                    let old_z = min.z + z - 1;
+                    if old_x < self.extent.x && old_y < self.extent.y && old_z < self.extent.z {
+                        new_voxels[new_extent.index(x, y, z)] =
+                            self.voxel_field[self.extent.index(old_x, old_y, old_z)];
                     }
                 }
             }
         }
 
         self.voxel_field = new_voxels;
-        self.field_size = new_size;
+        self.extent = new_extent;
+        self.invalidate_mesh_blocks();
     }
 
-    pub fn increase_field_size(&mut self) {
+    /// Grows the field so that it is at least `required` voxels long along each axis
+    /// Voxels keep their coordinates, new voxels are empty
+    pub fn increase_field_size(&mut self, required: FieldExtent) {
         let start = Instant::now();
 
-        let mut min = VoxelCoords::new(u8::MAX, u8::MAX, u8::MAX);
-        let mut max = VoxelCoords::new(0, 0, 0);
-        let mut has_active_voxels = false;
-
-        let chunk_size = 8; // AVX processes 8 floats at a time
-        let mut i = 0;
-        let threshold = unsafe { _mm256_set1_ps(0.0) };
-
-        while i + chunk_size <= self.voxel_field.len() {
-            unsafe {
-                let values_ptr = self.voxel_field.as_ptr().add(i) as *const f32;
-                let values = _mm256_load_ps(values_ptr); // Load 8 voxel values at once
-                let mask = _mm256_cmp_ps(values, threshold, _CMP_GE_OQ);
-                let bitmask = _mm256_movemask_ps(mask) as u8;
-
-                if bitmask != 0 {
-                    has_active_voxels = true;
-                    for j in 0..chunk_size {
-                        if (bitmask & (1 << j)) != 0 {
-                            let coords = self.index_to_coords(i + j);
-                            min.x = min.x.min(coords.x);
-                            min.y = min.y.min(coords.y);
-                            min.z = min.z.min(coords.z);
-                            max.x = max.x.max(coords.x);
-                            max.y = max.y.max(coords.y);
-                            max.z = max.z.max(coords.z);
-                        }
-                    }
-                }
-            }
-            i += chunk_size;
-        }
-
-        // Scalar cleanup for remaining elements (if any)
-        while i < self.voxel_field.len() {
-            if self.voxel_field[i].value >= 0.0 {
-                has_active_voxels = true;
-                let coords = self.index_to_coords(i);
-                min.x = min.x.min(coords.x);
-                min.y = min.y.min(coords.y);
-                min.z = min.z.min(coords.z);
-                max.x = max.x.max(coords.x);
-                max.y = max.y.max(coords.y);
-                max.z = max.z.max(coords.z);
-            }
-            i += 1;
-        }
-
-        if !has_active_voxels {
-            min = VoxelCoords::new(0, 0, 0);
-            max = VoxelCoords::new(
-                (self.field_size - 1) as u8,
-                (self.field_size - 1) as u8,
-                (self.field_size - 1) as u8,
-            );
-        }
-
-        let pad = 3;
-        let new_size_x = max.x as usize - min.x as usize + pad;
-        let new_size_y = max.y as usize - min.y as usize + pad;
-        let new_size_z = max.z as usize - min.z as usize + pad;
-        let new_size = new_size_x.max(new_size_y).max(new_size_z);
+        let new_extent = FieldExtent::new(
+            self.extent.x.max(required.x),
+            self.extent.y.max(required.y),
+            self.extent.z.max(required.z),
+        );
 
-        if new_size > self.field_size {
-            let old_size = self.field_size;
+        if new_extent != self.extent {
+            let old_extent = self.extent;
             let old_data = std::mem::take(&mut self.voxel_field);
 
             self.voxel_field = vec![
@@ -268,105 +884,507 @@ impl ProceduralEntity {
                     value: -1.0,
                     material: VoxelMaterial::AIR,
                 };
-                new_size * new_size * new_size
+                new_extent.volume()
             ];
 
-            println!("Bounding box: min={:?}, max={:?}", min, max);
-            println!("New size: {}", new_size);
-
             for (i, voxel) in old_data.into_iter().enumerate() {
-                let old_coords = self.index_to_coords(i);
-                let new_coords = old_coords;
-                let new_index = new_coords.z as usize
-                    + new_coords.y as usize * new_size
-                    + new_coords.x as usize * new_size * new_size;
-                if new_index < self.voxel_field.len() {
-                    self.voxel_field[new_index] = voxel;
-                }
+                let c = old_extent.coords(i);
+                self.voxel_field[new_extent.index(c.x, c.y, c.z)] = voxel;
             }
 
-            self.field_size = new_size;
+            self.extent = new_extent;
+            self.invalidate_mesh_blocks();
         }
 
-        let duration = start.elapsed();
-        println!("increase_field_size duration: {:?}", duration);
+        self.stats.resize_time = start.elapsed();
     }
 
+    /// Carves the shape of a brush (whose radius is in voxels) out of the field, centred on
+    /// `hit_position` (expressed in the local space of the entity), cylinders and cones being
+    /// aligned on axis
+    /// This is a CSG subtraction, each voxel moving towards it by the strength and weight of the
+    /// brush, and only by the fraction its material does not resist (see
+    /// MaterialProperties::hardness): carving the same spot again digs further
+    /// Voxels of the brush falling outside of the field are ignored, fails if the hit itself is
+    /// outside of the field
     pub fn carve(
         &mut self,
         hit_position: Vec3,
-        carve_speed: f32,
-        carve_radius: usize,
-    ) -> Vec<ProceduralEntity> {
-        let converted = WorldCoords::from(hit_position);
-        let voxel_coords = VoxelCoords::new(
-            converted.x.into_inner() as u8,
-            converted.y.into_inner() as u8,
-            converted.z.into_inner() as u8,
-        );
+        axis: Vec3,
+        brush: &Brush,
+        materials: &MaterialRegistry,
+    ) -> EditResult {
+        let report = self.edit_symmetric(hit_position, axis, |entity, hit_position, axis| {
+            LocalVoxelCoords::from_local_point(hit_position, entity.extent)?;
 
-        for c in voxel_coords.iter_around(carve_radius) {
-            let v = VoxelCoords::new(c.x as u8, c.y as u8, c.z as u8);
-            let index = v.z as usize
-                + v.y as usize * self.field_size
-                + v.x as usize * self.field_size * self.field_size;
-            if index >= self.voxel_field.len() {
-                continue;
-                // return Vec::new();
+            let sdf = brush.sdf(hit_position, axis);
+            // the falloff also covers the voxels of the density band around the shape, so that
+            // a constant brush at full strength gives it exactly
+            let reach = Brush {
+                radius: brush.radius + DENSITY_BAND,
+                ..*brush
+            };
+            let (min, max) = Self::brush_bounds(hit_position, brush);
+            let report = entity.edit_values(
+                (min, max),
+                |material| materials.properties(material).hardness,
+                |value, pos| {
+                    let target = value.min(sdf.distance(pos));
+                    value
+                        + (target - value) * brush.strength * reach.weight(pos - hit_position, axis)
+                },
+            );
+            entity.modification_count += report.changed;
+            entity.mark_dirty(min, max);
+            Ok(report)
+        })?;
+
+        Ok((self.extract_regions(), report))
+    }
+
+    /// Adds the shape of a brush (whose radius is in voxels) to the field, centred on
+    /// `hit_position` (expressed in the local space of the entity), cylinders and cones being
+    /// aligned on axis
+    /// This is a CSG union, applied by the strength and weight of the brush and resisted by the
+    /// hardness of the materials like carve: empty voxels count as made of the material of the
+    /// matter around them, which they are made of once filled (see edit_values)
+    /// The field grows if the brush goes past its end, voxels of the brush with negative
+    /// coordinates are ignored, fails if the hit itself is outside of the field
+    pub fn fill(
+        &mut self,
+        hit_position: Vec3,
+        axis: Vec3,
+        brush: &Brush,
+        materials: &MaterialRegistry,
+    ) -> EditResult {
+        let report = self.edit_symmetric(hit_position, axis, |entity, hit_position, axis| {
+            LocalVoxelCoords::from_local_point(hit_position, entity.extent)?;
+
+            // make room for the filled voxels, keeping an empty voxel after them so that the
+            // surface gets closed
+            let (min, max) = Self::brush_bounds(hit_position, brush);
+            entity.increase_field_size(FieldExtent::new(
+                max.x.max(0) as usize + 2,
+                max.y.max(0) as usize + 2,
+                max.z.max(0) as usize + 2,
+            ));
+
+            let sdf = brush.sdf(hit_position, axis);
+            // the falloff also covers the voxels of the density band around the shape, so that
+            // a constant brush at full strength gives it exactly
+            let reach = Brush {
+                radius: brush.radius + DENSITY_BAND,
+                ..*brush
+            };
+            let report = entity.edit_values(
+                (min, max),
+                |material| materials.properties(material).hardness,
+                |value, pos| {
+                    let target = value.max(-sdf.distance(pos));
+                    value
+                        + (target - value) * brush.strength * reach.weight(pos - hit_position, axis)
+                },
+            );
+            entity.mark_dirty(min, max);
+            Ok(report)
+        })?;
+
+        Ok((vec![self.clone()], report))
+    }
+
+    /// Blurs the field inside a brush (whose radius is in voxels) centred on `hit_position`
+    /// (expressed in the local space of the entity), each voxel moving towards the average of
+    /// its neighbours by the strength of the brush
+    pub fn smooth(
+        &mut self,
+        hit_position: Vec3,
+        axis: Vec3,
+        brush: &Brush,
+        materials: &MaterialRegistry,
+    ) -> EditResult {
+        self.apply_tool(
+            hit_position,
+            axis,
+            brush,
+            materials,
+            1.,
+            |density, pos, _, _| {
+                let mut sum = 0.;
+                for offset in [Vec3::X, Vec3::Y, Vec3::Z] {
+                    sum += density(pos + offset) + density(pos - offset);
+                }
+                sum / 6.
+            },
+        )
+    }
+
+    /// Moves the field inside a brush (whose radius is in voxels) centred on `hit_position`
+    /// (expressed in the local space of the entity) towards the plane going through the hit,
+    /// with the given normal
+    pub fn flatten(
+        &mut self,
+        hit_position: Vec3,
+        normal: Vec3,
+        brush: &Brush,
+        materials: &MaterialRegistry,
+    ) -> EditResult {
+        let normal = normal.normalize_or(Vec3::Y);
+        self.apply_tool(
+            hit_position,
+            normal,
+            brush,
+            materials,
+            0.,
+            |_, pos, hit_position, normal| {
+                // solid below the plane
+                -(pos - hit_position).dot(normal)
+            },
+        )
+    }
+
+    /// Drags the field inside a brush (whose radius is in voxels) centred on `hit_position`
+    /// (expressed in the local space of the entity) along stroke, the move of the brush since
+    /// the previous edit
+    pub fn smear(
+        &mut self,
+        hit_position: Vec3,
+        stroke: Vec3,
+        brush: &Brush,
+        materials: &MaterialRegistry,
+    ) -> EditResult {
+        self.apply_tool(
+            hit_position,
+            stroke,
+            brush,
+            materials,
+            stroke.length(),
+            |density, pos, _, stroke| density(pos - stroke),
+        )
+    }
+
+    /// Sets the material of the solid voxels inside a brush (whose radius is in voxels) centred
+    /// on `hit_position` (expressed in the local space of the entity), without changing their
+    /// value, so the shape stays the same
+    /// Fails if the hit is outside of the field
+    pub fn paint(
+        &mut self,
+        hit_position: Vec3,
+        axis: Vec3,
+        brush: &Brush,
+        material: VoxelMaterial,
+    ) -> EditResult {
+        let report = self.edit_symmetric(hit_position, axis, |entity, hit_position, axis| {
+            LocalVoxelCoords::from_local_point(hit_position, entity.extent)?;
+
+            let (min, max) = Self::brush_bounds(hit_position, brush);
+            let mut report = EditReport::default();
+            for x in min.x..=max.x {
+                for y in min.y..=max.y {
+                    for z in min.z..=max.z {
+                        if !entity.extent.contains(x as i64, y as i64, z as i64) {
+                            continue;
+                        }
+                        let pos = Vec3::new(x as f32, y as f32, z as f32);
+                        if brush.weight(pos - hit_position, axis) <= 0. {
+                            continue;
+                        }
+                        let index = entity.extent.index(x as usize, y as usize, z as usize);
+                        let voxel = &mut entity.voxel_field[index];
+                        if voxel.value >= 0. && voxel.material != material {
+                            voxel.material = material;
+                            report.changed += 1;
+                        }
+                    }
+                }
             }
+            if report.changed > 0 {
+                entity.mark_dirty(min, max);
+            }
+            Ok(report)
+        })?;
 
-            self.voxel_field[index].value -= carve_speed;
-            self.modification_count += 1; // Increment here for each voxel change
-        }
+        Ok((vec![self.clone()], report))
+    }
+
+    /// Pastes a clip with its origin on `position` (expressed in the local space of the entity),
+    /// rotated by rotation(direction), its voxels being resampled when the rotation is not made
+    /// of 90 degree turns
+    /// The field grows if a clip adding matter goes past its end, fails if the paste position is
+    /// outside of the field
+    pub fn paste(
+        &mut self,
+        clip: &VoxelClip,
+        position: Vec3,
+        direction: Vec3,
+        rotation: impl Fn(Vec3) -> Quat,
+        blend: BlendMode,
+    ) -> EditResult {
+        let report = self.edit_symmetric(position, direction, |entity, position, direction| {
+            LocalVoxelCoords::from_local_point(position, entity.extent)?;
+
+            let rotation = rotation(direction);
+            let (min, max) = clip.bounds(position, rotation);
+            if blend != BlendMode::Subtract {
+                entity.increase_field_size(FieldExtent::new(
+                    max.x.max(0) as usize + 2,
+                    max.y.max(0) as usize + 2,
+                    max.z.max(0) as usize + 2,
+                ));
+            }
+
+            let inverse = rotation.inverse();
+            let mut report = EditReport::default();
+            for x in min.x..=max.x {
+                for y in min.y..=max.y {
+                    for z in min.z..=max.z {
+                        if !entity.extent.contains(x as i64, y as i64, z as i64) {
+                            continue;
+                        }
+                        let pos = Vec3::new(x as f32, y as f32, z as f32);
+                        let Some(pasted) = clip.sample(inverse * (pos - position) + clip.origin)
+                        else {
+                            continue;
+                        };
+                        let index = entity.extent.index(x as usize, y as usize, z as usize);
+                        let voxel = &mut entity.voxel_field[index];
+                        let (value, material) = match blend {
+                            BlendMode::Replace => (pasted.value, pasted.material),
+                            BlendMode::Union if pasted.value >= voxel.value.max(0.) => {
+                                (pasted.value, pasted.material)
+                            }
+                            BlendMode::Union => (voxel.value.max(pasted.value), voxel.material),
+                            BlendMode::Subtract => (voxel.value.min(-pasted.value), voxel.material),
+                        };
+                        let value = value.clamp(-DENSITY_BAND, DENSITY_BAND);
+                        if value != voxel.value {
+                            // matter removed from the material it was made of
+                            let recorded = if value < voxel.value {
+                                voxel.material
+                            } else {
+                                material
+                            };
+                            report.record(recorded, voxel.value, value);
+                        } else if material != voxel.material {
+                            report.changed += 1;
+                        }
+                        *voxel = Voxel { value, material };
+                    }
+                }
+            }
+            entity.modification_count += report.changed;
+            entity.mark_dirty(min, max);
+            Ok(report)
+        })?;
 
-        self.extract_regions()
+        Ok((self.extract_regions(), report))
     }
 
-    pub fn fill(
+    /// Moves the voxels of the field inside a brush towards target(density, position, hit
+    /// position, axis), density sampling the field before the edit, by the strength and weight
+    /// of the brush, resisted by the toughness of their material
+    /// Targets only sample the field up to reach voxels away from the position they are given
+    /// The field grows if the brush goes past its end
+    fn apply_tool(
         &mut self,
         hit_position: Vec3,
-        fill_speed: f32,
-        fill_radius: usize,
-    ) -> Vec<ProceduralEntity> {
-        let converted = WorldCoords::from(hit_position);
-        let voxel_coords = VoxelCoords::new(
-            converted.x.into_inner() as u8,
-            converted.y.into_inner() as u8,
-            converted.z.into_inner() as u8,
-        );
+        axis: Vec3,
+        brush: &Brush,
+        materials: &MaterialRegistry,
+        reach: f32,
+        target: impl Fn(&dyn Fn(Vec3) -> f32, Vec3, Vec3, Vec3) -> f32,
+    ) -> EditResult {
+        let report = self.edit_symmetric(hit_position, axis, |entity, hit_position, axis| {
+            LocalVoxelCoords::from_local_point(hit_position, entity.extent)?;
+
+            // tools can add matter anywhere in the brush, like fill
+            let (min, max) = Self::brush_bounds(hit_position, brush);
+            entity.increase_field_size(FieldExtent::new(
+                max.x.max(0) as usize + 2,
+                max.y.max(0) as usize + 2,
+                max.z.max(0) as usize + 2,
+            ));
 
-        let old_size = self.field_size;
-        let mut should_increase = false;
-
-        for c in voxel_coords.iter_around(fill_radius) {
-            let v = VoxelCoords::new(c.x as u8, c.y as u8, c.z as u8);
-            if v.x >= self.field_size as u8
-                || v.y >= self.field_size as u8
-                || v.z >= self.field_size as u8
-            {
-                should_increase = true;
-                break;
+            // copy of the part of the field the targets can sample, the brush bounds grown by
+            // the reach and a voxel for the interpolation
+            let margin = reach.ceil() as i32 + 1;
+            let last = IVec3::new(
+                entity.extent.x as i32 - 1,
+                entity.extent.y as i32 - 1,
+                entity.extent.z as i32 - 1,
+            );
+            let copy_min = (IVec3::new(min.x, min.y, min.z) - margin).clamp(IVec3::ZERO, last);
+            let copy_max = (IVec3::new(max.x, max.y, max.z) + margin).clamp(IVec3::ZERO, last);
+            let copy_extent = FieldExtent::new(
+                (copy_max.x - copy_min.x + 1) as usize,
+                (copy_max.y - copy_min.y + 1) as usize,
+                (copy_max.z - copy_min.z + 1) as usize,
+            );
+            let mut copy = Vec::with_capacity(copy_extent.volume());
+            for x in copy_min.x..=copy_max.x {
+                for y in copy_min.y..=copy_max.y {
+                    for z in copy_min.z..=copy_max.z {
+                        copy.push(
+                            entity.voxel_field
+                                [entity.extent.index(x as usize, y as usize, z as usize)],
+                        );
+                    }
+                }
             }
-            let index = v.z as usize
-                + v.y as usize * self.field_size
-                + v.x as usize * self.field_size * self.field_size;
-            if index < self.voxel_field.len() {
-                self.voxel_field[index].value += fill_speed;
-                self.voxel_field[index].value = self.voxel_field[index].value.min(1.0);
+            let offset = copy_min.as_vec3();
+            let density = |pos: Vec3| sample_density(&copy, copy_extent, pos - offset);
+            let report = entity.edit_values(
+                (min, max),
+                |material| materials.properties(material).toughness,
+                |value, pos| {
+                    let weight = brush.strength * brush.weight(pos - hit_position, axis);
+                    if weight <= 0. {
+                        return value;
+                    }
+                    value + (target(&density, pos, hit_position, axis) - value) * weight
+                },
+            );
+            entity.modification_count += report.changed;
+            entity.mark_dirty(min, max);
+            Ok(report)
+        })?;
+
+        Ok((self.extract_regions(), report))
+    }
+
+    /// Makes an edit at `position` along `direction` (axis of the brush, normal or stroke), then
+    /// at the symmetric positions of the entity (see Symmetry::images), adding up their reports
+    /// The copies falling outside of the field are skipped, fails if the edit itself is outside
+    /// of it
+    fn edit_symmetric(
+        &mut self,
+        position: Vec3,
+        direction: Vec3,
+        mut edit: impl FnMut(&mut Self, Vec3, Vec3) -> Result<EditReport, OutOfFieldError>,
+    ) -> Result<EditReport, OutOfFieldError> {
+        let images = self.symmetry.images(position, direction);
+        let mut report = edit(self, position, direction)?;
+        for (position, direction) in images.into_iter().skip(1) {
+            if let Ok(copy) = edit(self, position, direction) {
+                report.merge(copy);
             }
         }
+        Ok(report)
+    }
 
-        if should_increase {
-            self.increase_field_size();
+    /// Bounding box of the voxels whose value can be changed by a brush centred on `center`
+    /// (the ones within the density band around its surface included)
+    pub fn brush_bounds(center: Vec3, brush: &Brush) -> (ICoords, ICoords) {
+        let half_extent = Vec3::splat(brush.half_extent() + DENSITY_BAND);
+        let min = (center - half_extent).floor();
+        let max = (center + half_extent).ceil();
+        (
+            ICoords {
+                x: min.x as i32,
+                y: min.y as i32,
+                z: min.z as i32,
+            },
+            ICoords {
+                x: max.x as i32,
+                y: max.y as i32,
+                z: max.z as i32,
+            },
+        )
+    }
+
+    /// Moves the value of the voxels of the field within bounds towards edit(value, position),
+    /// by the fraction of the move not resisted by their material (resistance(material), between
+    /// 0 and 1), clamped to the density band
+    /// Voxels made of air gaining matter are made of the material of most of their solid
+    /// neighbours (of most of the solid voxels within bounds when they have none, stone when
+    /// there are none either), which resists the edit and which they keep if they become solid
+    fn edit_values(
+        &mut self,
+        (min, max): (ICoords, ICoords),
+        resistance: impl Fn(VoxelMaterial) -> f32,
+        edit: impl Fn(f32, Vec3) -> f32,
+    ) -> EditReport {
+        let mut report = EditReport::default();
+        let mut bounds_material = None;
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    if !self.extent.contains(x as i64, y as i64, z as i64) {
+                        continue;
+                    }
+                    let index = self.extent.index(x as usize, y as usize, z as usize);
+                    let voxel = self.voxel_field[index];
+                    let pos = Vec3::new(x as f32, y as f32, z as f32);
+                    let target = edit(voxel.value, pos);
+                    let mut material = voxel.material;
+                    if material == VoxelMaterial::AIR && target > voxel.value {
+                        let neighbours = (
+                            ICoords {
+                                x: x - 1,
+                                y: y - 1,
+                                z: z - 1,
+                            },
+                            ICoords {
+                                x: x + 1,
+                                y: y + 1,
+                                z: z + 1,
+                            },
+                        );
+                        material = self.dominant_material(neighbours).unwrap_or_else(|| {
+                            *bounds_material.get_or_insert_with(|| {
+                                self.dominant_material((min, max))
+                                    .unwrap_or(VoxelMaterial::STONE)
+                            })
+                        });
+                    }
+                    let yielding = 1. - resistance(material).clamp(0., 1.);
+                    let value = (voxel.value + (target - voxel.value) * yielding)
+                        .clamp(-DENSITY_BAND, DENSITY_BAND);
+                    if value != voxel.value {
+                        let material = if value >= 0. {
+                            material
+                        } else {
+                            voxel.material
+                        };
+                        report.record(material, voxel.value, value);
+                        self.voxel_field[index] = Voxel { value, material };
+                    }
+                }
+            }
         }
+        report
+    }
 
-        vec![self.clone()]
+    /// Material of most of the solid voxels of the field within bounds (included), air aside
+    fn dominant_material(&self, (min, max): (ICoords, ICoords)) -> Option<VoxelMaterial> {
+        let mut counts: HashMap<VoxelMaterial, usize> = HashMap::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    if !self.extent.contains(x as i64, y as i64, z as i64) {
+                        continue;
+                    }
+                    let voxel =
+                        self.voxel_field[self.extent.index(x as usize, y as usize, z as usize)];
+                    if voxel.value >= 0. && voxel.material != VoxelMaterial::AIR {
+                        *counts.entry(voxel.material).or_default() += 1;
+                    }
+                }
+            }
+        }
+        // the lowest material on ties, for results that do not depend on the hash map
+        counts
+            .into_iter()
+            .max_by_key(|&(material, count)| (count, std::cmp::Reverse(material.0)))
+            .map(|(material, _)| material)
     }
 
     /// Extract and return new entities for each connected region in the voxel field
-    fn extract_regions(&self) -> Vec<ProceduralEntity> {
+    /// When all the voxels still form a single region, the entity is kept as is (along with its
+    /// cached mesh blocks)
+    pub fn extract_regions(&self) -> Vec<ProceduralEntity> {
         let mut visited = vec![false; self.voxel_field.len()];
         let mut new_entities = Vec::new();
+        let solid_voxel_count = self.voxel_field.iter().filter(|v| v.value >= 0.0).count();
 
         for (i, voxel) in self.voxel_field.iter().enumerate() {
             if !visited[i] && voxel.value >= 0.0 {
@@ -381,7 +1399,7 @@ impl ProceduralEntity {
                     if v.value > 0.0 {
                         region_voxels[j].value = -0.1;
                     } else {
-                        region_voxels[j].value = v.value;
+                        region_voxels[j] = *v;
                     }
                 }
                 let mut positive_voxel_count = 0;
@@ -393,12 +1411,31 @@ impl ProceduralEntity {
                     &mut positive_voxel_count,
                 );
 
+                if positive_voxel_count == solid_voxel_count {
+                    return vec![self.clone()];
+                }
+
                 if positive_voxel_count > 1 {
                     let new_entity = ProceduralEntity {
                         // pos: self.pos, // You might want to adjust the position based on the region's location
-                        field_size: self.field_size, // Initialize field size
+                        extent: self.extent, // Initialize field size
                         voxel_field: region_voxels,
                         vertices: Vec::new(),
+                        indices: Vec::new(),
+                        normal_mode: self.normal_mode,
+                        texture_scale: self.texture_scale,
+                        mesher: self.mesher,
+                        ambient_occlusion: self.ambient_occlusion,
+                        render_simplification: self.render_simplification,
+                        collider_simplification: self.collider_simplification,
+                        symmetry: self.symmetry,
+                        mesh_blocks: Vec::new(),
+                        dirty_blocks: Vec::new(),
+                        mesh_blocks_settings: None,
+                        lod_levels: Vec::new(),
+                        simplified_meshes: Vec::new(),
+                        simplified_collider: None,
+                        stats: MeshStats::default(),
 
                         modification_count: self.modification_count,
                         modification_threshold: self.modification_threshold,
@@ -434,30 +1471,21 @@ impl ProceduralEntity {
                 *positive_voxel_count += 1;
             }
 
-            let x = index / (self.field_size * self.field_size);
-            let y = (index % (self.field_size * self.field_size)) / self.field_size;
-            let z = index % self.field_size;
+            let c = self.extent.coords(index);
+            let (x, y, z) = (c.x as i64, c.y as i64, c.z as i64);
 
             let neighbors = [
-                (x as isize + 1, y as isize, z as isize),
-                (x as isize - 1, y as isize, z as isize),
-                (x as isize, y as isize + 1, z as isize),
-                (x as isize, y as isize - 1, z as isize),
-                (x as isize, y as isize, z as isize + 1),
-                (x as isize, y as isize, z as isize - 1),
+                (x + 1, y, z),
+                (x - 1, y, z),
+                (x, y + 1, z),
+                (x, y - 1, z),
+                (x, y, z + 1),
+                (x, y, z - 1),
             ];
 
             for (nx, ny, nz) in neighbors {
-                if nx >= 0
-                    && nx < self.field_size as isize
-                    && ny >= 0
-                    && ny < self.field_size as isize
-                    && nz >= 0
-                    && nz < self.field_size as isize
-                {
-                    let n_index = (nx as usize) * self.field_size * self.field_size
-                        + (ny as usize) * self.field_size
-                        + (nz as usize);
+                if self.extent.contains(nx, ny, nz) {
+                    let n_index = self.extent.index(nx as usize, ny as usize, nz as usize);
                     if !visited[n_index] && self.voxel_field[n_index].value >= 0.0 {
                         queue.push_back(n_index);
                     }
@@ -473,9 +1501,16 @@ impl ProceduralEntity {
  */
 impl Clone for ProceduralEntity {
     fn clone(&self) -> Self {
-        let mut new_entity = ProceduralEntity::new(self.field_size);
+        let mut new_entity = ProceduralEntity::new(self.extent);
         new_entity.modification_count = self.modification_count;
         new_entity.modification_threshold = self.modification_threshold;
+        new_entity.normal_mode = self.normal_mode;
+        new_entity.texture_scale = self.texture_scale;
+        new_entity.mesher = self.mesher;
+        new_entity.ambient_occlusion = self.ambient_occlusion;
+        new_entity.render_simplification = self.render_simplification;
+        new_entity.collider_simplification = self.collider_simplification;
+        new_entity.symmetry = self.symmetry;
         for i in 0..self.voxel_field.len() {
             new_entity.voxel_field.push(self.voxel_field[i]);
         }
@@ -483,7 +1518,56 @@ impl Clone for ProceduralEntity {
         for v in self.vertices.iter() {
             new_entity.vertices.push(v.clone());
         }
+        new_entity.indices = self.indices.clone();
+        new_entity.mesh_blocks = self.mesh_blocks.clone();
+        new_entity.dirty_blocks = self.dirty_blocks.clone();
+        new_entity.mesh_blocks_settings = self.mesh_blocks_settings;
+        new_entity.lod_levels = self.lod_levels.clone();
+        new_entity.simplified_meshes = self.simplified_meshes.clone();
+        new_entity.simplified_collider = self.simplified_collider.clone();
+        new_entity.stats = self.stats;
 
         new_entity
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generators::{GeneratorKind, ShapeGenerator};
+    use bevy::tasks::TaskPoolBuilder;
+
+    /// Bits of the positions, normals and materials of the vertices, for exact comparisons
+    fn vertex_bits(vertices: &[Vertex]) -> Vec<([u32; 6], u16)> {
+        vertices
+            .iter()
+            .map(|v| {
+                let (p, n) = (v.pos, v.normal);
+                (
+                    [p.x.0, p.y.0, p.z.0, n.x, n.y, n.z].map(f32::to_bits),
+                    v.voxel_material.0,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn meshing_does_not_depend_on_the_thread_count() {
+        let entity =
+            ShapeGenerator::new(GeneratorKind::Asteroid, 3).generate(FieldExtent::cube(40));
+        let meshes: Vec<_> = [1, 4]
+            .into_iter()
+            .map(|threads| {
+                let pool = TaskPoolBuilder::new().num_threads(threads).build();
+                let mut entity = entity.clone();
+                entity.generate_vertices_on(&pool);
+                (vertex_bits(&entity.vertices), entity.indices)
+            })
+            .collect();
+        assert!(!meshes[0].1.is_empty());
+        assert!(
+            meshes[0] == meshes[1],
+            "meshes differ between 1 and 4 threads"
+        );
+    }
+}
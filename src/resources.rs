@@ -5,6 +5,7 @@ use std::collections::VecDeque;
 use std::sync::Arc;
 use std::sync::Mutex;
 
+use crate::common::voxel_material::VoxelMaterial;
 use crate::procedural_entity::*;
 
 /// All procedurally generated entities
@@ -21,3 +22,54 @@ pub struct RayMeshHits(pub VecDeque<(Entity, RayMeshHit)>);
 
 #[derive(Resource, Default)]
 pub struct FillMode(pub bool); // true for fill, false for carve:
+
+/// Sculpting tool applied where the camera ray hits an entity
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SculptTool {
+    /// Carves or fills, depending on FillMode
+    #[default]
+    Deform,
+    Smooth,
+    Flatten,
+    Smear,
+    /// Changes the material of the voxels, not their shape
+    Paint,
+    /// Copies the voxels inside the brush to the VoxelClipboard
+    Copy,
+    /// Copies the voxels of the box between two corners clicked on an entity, grown by the
+    /// radius of the brush, to the VoxelClipboard
+    CopyBox,
+    /// Pastes the VoxelClipboard where the ray hits
+    Paste,
+}
+
+#[derive(Resource, Default)]
+pub struct ActiveTool(pub SculptTool);
+
+/// Material applied by the paint tool
+#[derive(Resource)]
+pub struct PaintMaterial(pub VoxelMaterial);
+
+impl Default for PaintMaterial {
+    fn default() -> Self {
+        Self(VoxelMaterial::STONE)
+    }
+}
+
+/// Matter removed and added by the last edit of a procedural entity, or why it failed
+#[derive(Resource, Default)]
+pub struct LastEditReport(pub Option<Result<EditReport, String>>);
+
+/// Camera distances at which entities switch to their next level of detail
+#[derive(Resource)]
+pub struct LodSettings {
+    pub distances: [f32; LOD_LEVELS - 1],
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            distances: [15.0, 30.0, 60.0],
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use bevy::math::{Quat, Vec2, Vec3, Vec3Swizzles};
+
+/// Signed distance function described as a tree of primitives combined by CSG operations
+/// Distances are negative inside the shape, and positions are given in voxels of the field the
+/// tree is sampled in (see ProceduralEntity::from_sdf)
+/// Cylinders, tori and cones are aligned on the y axis, Transform orients them differently
+#[derive(Clone, PartialEq, Debug)]
+pub enum SdfNode {
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    Box {
+        center: Vec3,
+        half_extents: Vec3,
+    },
+    /// Box whose edges and corners are rounded with the given radius, within its half extents
+    RoundedBox {
+        center: Vec3,
+        half_extents: Vec3,
+        radius: f32,
+    },
+    /// Segment from a to b, inflated by radius
+    Capsule {
+        a: Vec3,
+        b: Vec3,
+        radius: f32,
+    },
+    /// Capped cylinder, going from center - half_height to center + half_height
+    Cylinder {
+        center: Vec3,
+        radius: f32,
+        half_height: f32,
+    },
+    /// Torus lying in the xz plane
+    Torus {
+        center: Vec3,
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    /// Cone with its base centred on base, and its apex height above it
+    Cone {
+        base: Vec3,
+        radius: f32,
+        height: f32,
+    },
+    /// Half space below the plane (on the opposite side of its normal)
+    Plane {
+        normal: Vec3,
+        // distance from the origin to the plane, along the normal
+        offset: f32,
+    },
+    Union(Box<SdfNode>, Box<SdfNode>),
+    Intersection(Box<SdfNode>, Box<SdfNode>),
+    /// The first shape minus the second one
+    Subtraction(Box<SdfNode>, Box<SdfNode>),
+    /// Union blending the shapes over a distance of about smoothness
+    SmoothUnion {
+        a: Box<SdfNode>,
+        b: Box<SdfNode>,
+        smoothness: f32,
+    },
+    /// a minus b, blending the shapes over a distance of about smoothness
+    SmoothSubtraction {
+        a: Box<SdfNode>,
+        b: Box<SdfNode>,
+        smoothness: f32,
+    },
+    /// Rotates a shape around the origin, then translates it
+    Transform {
+        node: Box<SdfNode>,
+        rotation: Quat,
+        translation: Vec3,
+    },
+}
+
+impl SdfNode {
+    pub fn sphere(center: Vec3, radius: f32) -> Self {
+        Self::Sphere { center, radius }
+    }
+
+    pub fn cuboid(center: Vec3, half_extents: Vec3) -> Self {
+        Self::Box {
+            center,
+            half_extents,
+        }
+    }
+
+    pub fn rounded_box(center: Vec3, half_extents: Vec3, radius: f32) -> Self {
+        Self::RoundedBox {
+            center,
+            half_extents,
+            radius,
+        }
+    }
+
+    pub fn capsule(a: Vec3, b: Vec3, radius: f32) -> Self {
+        Self::Capsule { a, b, radius }
+    }
+
+    pub fn cylinder(center: Vec3, radius: f32, half_height: f32) -> Self {
+        Self::Cylinder {
+            center,
+            radius,
+            half_height,
+        }
+    }
+
+    pub fn torus(center: Vec3, major_radius: f32, minor_radius: f32) -> Self {
+        Self::Torus {
+            center,
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    pub fn cone(base: Vec3, radius: f32, height: f32) -> Self {
+        Self::Cone {
+            base,
+            radius,
+            height,
+        }
+    }
+
+    pub fn plane(normal: Vec3, offset: f32) -> Self {
+        Self::Plane { normal, offset }
+    }
+
+    pub fn union(self, other: SdfNode) -> Self {
+        Self::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersection(self, other: SdfNode) -> Self {
+        Self::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn subtraction(self, other: SdfNode) -> Self {
+        Self::Subtraction(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: SdfNode, smoothness: f32) -> Self {
+        Self::SmoothUnion {
+            a: Box::new(self),
+            b: Box::new(other),
+            smoothness,
+        }
+    }
+
+    pub fn smooth_subtraction(self, other: SdfNode, smoothness: f32) -> Self {
+        Self::SmoothSubtraction {
+            a: Box::new(self),
+            b: Box::new(other),
+            smoothness,
+        }
+    }
+
+    pub fn transformed(self, rotation: Quat, translation: Vec3) -> Self {
+        Self::Transform {
+            node: Box::new(self),
+            rotation,
+            translation,
+        }
+    }
+
+    /// Signed distance from pos to the surface of the shape
+    /// Distances are exact for the primitives, and a bound of the exact distance for the
+    /// combinations (the surface is right, but the distance far from it can be too short)
+    pub fn distance(&self, pos: Vec3) -> f32 {
+        match self {
+            SdfNode::Sphere { center, radius } => (pos - *center).length() - radius,
+            SdfNode::Box {
+                center,
+                half_extents,
+            } => box_distance(pos - *center, *half_extents),
+            SdfNode::RoundedBox {
+                center,
+                half_extents,
+                radius,
+            } => {
+                let radius = radius.min(half_extents.min_element());
+                box_distance(pos - *center, *half_extents - Vec3::splat(radius)) - radius
+            }
+            SdfNode::Capsule { a, b, radius } => {
+                let (pa, ba) = (pos - *a, *b - *a);
+                let h = (pa.dot(ba) / ba.length_squared().max(f32::EPSILON)).clamp(0., 1.);
+                (pa - ba * h).length() - radius
+            }
+            SdfNode::Cylinder {
+                center,
+                radius,
+                half_height,
+            } => {
+                let p = pos - *center;
+                let d = Vec2::new(p.xz().length() - radius, p.y.abs() - half_height);
+                d.max_element().min(0.) + d.max(Vec2::ZERO).length()
+            }
+            SdfNode::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => {
+                let p = pos - *center;
+                Vec2::new(p.xz().length() - major_radius, p.y).length() - minor_radius
+            }
+            SdfNode::Cone {
+                base,
+                radius,
+                height,
+            } => cone_distance(pos - *base, *radius, *height),
+            SdfNode::Plane { normal, offset } => pos.dot(normal.normalize_or_zero()) - offset,
+            SdfNode::Union(a, b) => a.distance(pos).min(b.distance(pos)),
+            SdfNode::Intersection(a, b) => a.distance(pos).max(b.distance(pos)),
+            SdfNode::Subtraction(a, b) => a.distance(pos).max(-b.distance(pos)),
+            SdfNode::SmoothUnion { a, b, smoothness } => {
+                let (a, b, k) = (
+                    a.distance(pos),
+                    b.distance(pos),
+                    smoothness.max(f32::EPSILON),
+                );
+                let h = (0.5 + 0.5 * (b - a) / k).clamp(0., 1.);
+                b + (a - b) * h - k * h * (1. - h)
+            }
+            SdfNode::SmoothSubtraction { a, b, smoothness } => {
+                let (a, b, k) = (
+                    a.distance(pos),
+                    b.distance(pos),
+                    smoothness.max(f32::EPSILON),
+                );
+                let h = (0.5 - 0.5 * (a + b) / k).clamp(0., 1.);
+                a + (-b - a) * h + k * h * (1. - h)
+            }
+            SdfNode::Transform {
+                node,
+                rotation,
+                translation,
+            } => node.distance(rotation.inverse() * (pos - *translation)),
+        }
+    }
+}
+
+/// Distance to a box centred on the origin
+fn box_distance(p: Vec3, half_extents: Vec3) -> f32 {
+    let q = p.abs() - half_extents;
+    q.max(Vec3::ZERO).length() + q.max_element().min(0.)
+}
+
+/// Distance to a cone with its base centred on the origin and its apex on the y axis
+fn cone_distance(p: Vec3, radius: f32, height: f32) -> f32 {
+    // in the plane going through the axis of the cone and p, centred on the middle of the axis
+    let q = Vec2::new(p.xz().length(), p.y - height / 2.);
+    let half_height = height / 2.;
+    // from the apex to the edge of the base
+    let side = Vec2::new(-radius, 2. * half_height);
+    let apex = Vec2::new(0., half_height);
+
+    let cap = Vec2::new(
+        q.x - q.x.min(if q.y < 0. { radius } else { 0. }),
+        q.y.abs() - half_height,
+    );
+    let t = ((apex - q).dot(side) / side.length_squared()).clamp(0., 1.);
+    let slope = q - apex + side * t;
+    let sign = if slope.x < 0. && cap.y < 0. { -1. } else { 1. };
+    sign * cap.length_squared().min(slope.length_squared()).sqrt()
+}
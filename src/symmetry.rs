@@ -0,0 +1,74 @@
+use bevy::math::{Quat, Vec3};
+
+/// How the edits of an entity are replicated
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum SymmetryKind {
+    #[default]
+    None,
+    /// Mirrors the edits across the planes going through the centre, normal to each chosen axis
+    /// (all the combinations of these mirrors, so up to 8 copies)
+    Mirror { x: bool, y: bool, z: bool },
+    /// count copies of the edits, evenly rotated around an axis going through the centre
+    /// Cube brushes stay aligned on the axes of the entity
+    Radial { axis: Vec3, count: usize },
+}
+
+/// Symmetry of the edits of an entity, in its local voxel space
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Symmetry {
+    pub kind: SymmetryKind,
+    // point the mirror planes and the radial axis go through, in voxels of the field
+    pub center: Vec3,
+}
+
+impl Symmetry {
+    pub fn mirror(center: Vec3, x: bool, y: bool, z: bool) -> Self {
+        Self {
+            kind: SymmetryKind::Mirror { x, y, z },
+            center,
+        }
+    }
+
+    pub fn radial(center: Vec3, axis: Vec3, count: usize) -> Self {
+        Self {
+            kind: SymmetryKind::Radial { axis, count },
+            center,
+        }
+    }
+
+    /// Positions and directions (axis of the brush, normal or stroke) of all the copies of an
+    /// edit made at `position` along `direction`, the edit itself being the first one
+    pub fn images(&self, position: Vec3, direction: Vec3) -> Vec<(Vec3, Vec3)> {
+        let offset = position - self.center;
+        match self.kind {
+            SymmetryKind::None => vec![(position, direction)],
+            SymmetryKind::Mirror { x, y, z } => {
+                let mut images = vec![(offset, direction)];
+                let mirrors = [
+                    (x, Vec3::new(-1., 1., 1.)),
+                    (y, Vec3::new(1., -1., 1.)),
+                    (z, Vec3::new(1., 1., -1.)),
+                ];
+                for (_, flip) in mirrors.into_iter().filter(|(mirrored, _)| *mirrored) {
+                    let flipped: Vec<_> =
+                        images.iter().map(|(o, d)| (*o * flip, *d * flip)).collect();
+                    images.extend(flipped);
+                }
+                images
+                    .into_iter()
+                    .map(|(o, d)| (self.center + o, d))
+                    .collect()
+            }
+            SymmetryKind::Radial { axis, count } => {
+                let axis = axis.normalize_or(Vec3::Y);
+                (0..count.max(1))
+                    .map(|i| {
+                        let angle = std::f32::consts::TAU * i as f32 / count.max(1) as f32;
+                        let rotation = Quat::from_axis_angle(axis, angle);
+                        (self.center + rotation * offset, rotation * direction)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
@@ -1,5 +1,9 @@
+use crate::brush::{ActiveBrush, Brush, BrushShape, Falloff};
 use crate::camera::*;
+use crate::clipboard::{BlendMode, VoxelClipboard};
 use crate::common::coords::*;
+use crate::common::voxel_material::{MaterialRegistry, VoxelMaterial};
+use crate::resources::{ActiveTool, FillMode, LastEditReport, PaintMaterial, SculptTool};
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContexts};
 
@@ -31,3 +35,124 @@ pub fn ui_main_system(
         // ui.label(format!("temperature: {}", temp));
     });
 }
+
+/// Active sculpting tool and settings of the active brush
+pub fn brush_ui_system(
+    mut contexts: EguiContexts,
+    mut brush: ResMut<ActiveBrush>,
+    mut tool: ResMut<ActiveTool>,
+    mut fill_mode: ResMut<FillMode>,
+    mut paint_material: ResMut<PaintMaterial>,
+    registry: Res<MaterialRegistry>,
+    mut clipboard: ResMut<VoxelClipboard>,
+) {
+    let brush = &mut brush.0;
+    egui::Window::new("Brush").show(contexts.ctx_mut(), |ui| {
+        egui::ComboBox::from_label("tool")
+            .selected_text(format!("{:?}", tool.0))
+            .show_ui(ui, |ui| {
+                for t in [
+                    SculptTool::Deform,
+                    SculptTool::Smooth,
+                    SculptTool::Flatten,
+                    SculptTool::Smear,
+                    SculptTool::Paint,
+                    SculptTool::Copy,
+                    SculptTool::CopyBox,
+                    SculptTool::Paste,
+                ] {
+                    ui.selectable_value(&mut tool.0, t, format!("{:?}", t));
+                }
+            });
+        ui.checkbox(&mut fill_mode.0, "fill (deform tool)");
+        let painted = registry
+            .get(paint_material.0)
+            .map_or("?", |m| m.name.as_str());
+        egui::ComboBox::from_label("paint material")
+            .selected_text(painted)
+            .show_ui(ui, |ui| {
+                for (material, definition) in registry.materials() {
+                    if material != VoxelMaterial::AIR {
+                        ui.selectable_value(&mut paint_material.0, material, &definition.name);
+                    }
+                }
+            });
+        egui::ComboBox::from_label("shape")
+            .selected_text(format!("{:?}", brush.shape))
+            .show_ui(ui, |ui| {
+                for shape in [
+                    BrushShape::Sphere,
+                    BrushShape::Cube,
+                    BrushShape::Cylinder,
+                    BrushShape::Cone,
+                ] {
+                    ui.selectable_value(&mut brush.shape, shape, format!("{:?}", shape));
+                }
+            });
+        egui::ComboBox::from_label("falloff")
+            .selected_text(format!("{:?}", brush.falloff))
+            .show_ui(ui, |ui| {
+                for falloff in [Falloff::Linear, Falloff::Smooth, Falloff::Constant] {
+                    ui.selectable_value(&mut brush.falloff, falloff, format!("{:?}", falloff));
+                }
+            });
+        ui.add(egui::Slider::new(&mut brush.radius, Brush::RADIUS_RANGE).text("radius"));
+        ui.add(egui::Slider::new(&mut brush.strength, Brush::STRENGTH_RANGE).text("strength"));
+        match &clipboard.clip {
+            Some(clip) => ui.label(format!("clipboard: {} voxels", clip.voxel_count())),
+            None => ui.label("clipboard: empty"),
+        };
+        if let Some((_, corner)) = clipboard.box_corner {
+            ui.label(format!("box corner at {}, click the other one", corner));
+        }
+        egui::ComboBox::from_label("paste blend")
+            .selected_text(format!("{:?}", clipboard.blend))
+            .show_ui(ui, |ui| {
+                for blend in [BlendMode::Replace, BlendMode::Union, BlendMode::Subtract] {
+                    ui.selectable_value(&mut clipboard.blend, blend, format!("{:?}", blend));
+                }
+            });
+        // the turn of the pasted clip is shown in degrees
+        let mut degrees = clipboard.angle.to_degrees();
+        if ui
+            .add(egui::Slider::new(&mut degrees, 0.0..=360.0).text("paste angle"))
+            .changed()
+        {
+            clipboard.angle = degrees.to_radians();
+        }
+    });
+}
+
+/// Matter removed and added by the last edit, or why it failed
+pub fn edit_report_ui_system(
+    mut contexts: EguiContexts,
+    last_report: Res<LastEditReport>,
+    registry: Res<MaterialRegistry>,
+) {
+    let Some(report) = &last_report.0 else {
+        return;
+    };
+    egui::Window::new("Last edit").show(contexts.ctx_mut(), |ui| {
+        let report = match report {
+            Ok(report) => report,
+            Err(err) => {
+                ui.label(err);
+                return;
+            }
+        };
+        ui.label(format!("{} voxels changed", report.changed));
+        for (verb, amounts) in [("removed", &report.removed), ("added", &report.added)] {
+            // in the order of the registry, so that lines do not move between frames
+            let mut amounts: Vec<_> = amounts.iter().collect();
+            amounts.sort_by_key(|(material, _)| material.0);
+            for (material, amount) in amounts {
+                let name = registry.get(*material).map_or("?", |m| m.name.as_str());
+                ui.label(format!("{} {} {:.2}", verb, name, amount));
+            }
+        }
+        ui.label(format!(
+            "removed mass {:.2}",
+            report.removed_mass(&registry)
+        ));
+    });
+}